@@ -1,13 +1,20 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::{self, BufRead, IsTerminal, Write};
 use std::path::{Path, PathBuf};
 use std::sync::LazyLock;
+use std::thread;
+use std::time::{Duration, Instant};
 use std::{fmt, fs};
 
 use anyhow::{anyhow, Context, Result};
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use cli_tools::fs_ops::{DryRunFs, FsOps, RealFs};
 use colored::Colorize;
 use itertools::Itertools;
 use regex::Regex;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
 use unicode_segmentation::UnicodeSegmentation;
 use walkdir::WalkDir;
 
@@ -25,6 +32,18 @@ static RE_EXCLAMATION: LazyLock<Regex> =
 static RE_DOTCOM: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"(?i)(\.com|\.net)\b").expect("Failed to compile .com regex"));
 
+static RE_EMPTY_BRACKETS: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\([.\s]*\)|\[[.\s]*\]|\{[.\s]*\}").expect("Failed to compile empty brackets regex"));
+
+static RE_SEPARATOR_RUN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"[._-]*\.[._-]*").expect("Failed to compile separator run regex"));
+
+static RE_BRACKETED_YEAR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"[\[(]\s*((?:19|20)\d{2})\s*[\])]").expect("Failed to compile bracketed year regex"));
+
+static RE_EPISODE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)^S\d{1,2}E\d{1,3}(E\d{1,3})*$").expect("Failed to compile episode regex"));
+
 static RE_IDENTIFIER: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"[A-Za-z0-9]{9,20}").expect("Failed to compile id regex"));
 
@@ -59,16 +78,81 @@ static REPLACE: [(&str, &str); 26] = [
 
 const RESOLUTIONS: [&str; 6] = ["540", "720", "1080", "1920", "2160", "3840"];
 
+/// Source tags recognized when reordering tokens.
+const SOURCE_TAGS: [&str; 14] = [
+    "AMZN", "BDRIP", "BLURAY", "BRRIP", "DSNP", "DVD", "DVDRIP", "HDTV", "NF", "REMUX", "WEB", "WEBDL", "WEBRIP",
+    "HDRIP",
+];
+
+/// Tags that belong to the preceding source tag, like "DL" in "WEB-DL".
+const SOURCE_SUFFIX_TAGS: [&str; 2] = ["DL", "RIP"];
+
+/// Codec tags recognized when reordering tokens.
+const CODEC_TAGS: [&str; 10] = [
+    "AV1", "AVC", "H264", "H265", "HEVC", "X264", "X265", "XVID", "10BIT", "VP9",
+];
+
+/// Default token order for --reorder.
+const DEFAULT_TOKEN_ORDER: [TokenClass; 6] = [
+    TokenClass::Title,
+    TokenClass::Year,
+    TokenClass::Episode,
+    TokenClass::Resolution,
+    TokenClass::Source,
+    TokenClass::Codec,
+];
+
+/// Maximum number of name parts between two identical years for the later one to be removed.
+const YEAR_DEDUPE_DISTANCE: usize = 2;
+
+/// Default delay between renames in gentle mode.
+const DEFAULT_GENTLE_DELAY_MS: u64 = 50;
+
+/// Default number of retries for temporary rename errors in gentle mode.
+const DEFAULT_RETRIES: u32 = 3;
+
+/// Maximum number of retries for temporary rename errors in gentle mode.
+const MAX_RETRIES: u32 = 10;
+
+/// Delay before checking a rename again when it could not be verified.
+const VERIFY_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// Extended attribute name for storing the original name.
+#[cfg(unix)]
+const ORIGINAL_NAME_ATTRIBUTE: &str = "user.dots.original_name";
+
+/// Placeholder for the file modification date in prefix and suffix templates.
+const DATE_PLACEHOLDER: &str = "{date}";
+
+/// Manifest file name for storing original names.
+const ORIGINALS_MANIFEST: &str = ".dots_originals.tsv";
+
+/// Per-directory config file name with extra formatting rules.
+const LOCAL_CONFIG: &str = ".dots.toml";
+
+/// Log file name for storing the renames of each run so they can be undone.
+const RENAME_LOG: &str = ".dots-rename-log.json";
+
+/// Number of most recent runs to keep in the rename log.
+const MAX_LOGGED_RUNS: usize = 10;
+
 #[derive(Debug, Parser)]
 #[command(author, version, name = "dots", about = "Rename files to use dots")]
 struct Args {
-    /// Optional input directory or file
-    path: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Optional input directories or files
+    paths: Vec<String>,
 
     /// Convert casing
     #[arg(short, long)]
     case: bool,
 
+    /// Warn if renaming would change the natural sort order of files in a directory
+    #[arg(long)]
+    check_order: bool,
+
     /// Enable debug prints
     #[arg(long)]
     debug: bool,
@@ -77,10 +161,102 @@ struct Args {
     #[arg(short, long)]
     directory: bool,
 
+    /// Resolve files differing only by unicode normalization by renaming the non-NFC ones
+    #[arg(long)]
+    dedupe_normalization: bool,
+
+    /// Move a file to trash instead of skipping it when it duplicates the existing file with the new name
+    #[arg(long)]
+    dedupe: bool,
+
+    /// Compare file contents for --dedupe instead of only the size
+    #[arg(long, requires = "dedupe")]
+    dedupe_verify: bool,
+
+    /// Which duplicate file to keep with --dedupe
+    #[arg(long, value_enum, value_name = "FILE", requires = "dedupe", default_value_t)]
+    dedupe_keep: DedupeKeep,
+
+    /// Skip files and directories whose name contains the pattern
+    #[arg(short, long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// Skip directories whose name contains the pattern without affecting files
+    #[arg(long, value_name = "PATTERN")]
+    exclude_dir: Vec<String>,
+
     /// Overwrite existing files
     #[arg(short, long)]
     force: bool,
 
+    /// Do not clean up leftover separators and empty brackets at the end of formatting
+    #[arg(long)]
+    no_final_cleanup: bool,
+
+    /// Do not read formatting rules from .dots.toml files in the input directories
+    #[arg(long)]
+    no_local_config: bool,
+
+    /// Keep bracketed years in parentheses like "Movie.Name.(2008)"
+    #[arg(long)]
+    year_brackets: bool,
+
+    /// Reorder recognized tokens like year, episode, resolution, source, and codec
+    #[arg(long)]
+    reorder: bool,
+
+    /// Only process files with a date in the name
+    #[arg(long, conflicts_with = "without_dates")]
+    with_dates: bool,
+
+    /// Only process files without a date in the name
+    #[arg(long)]
+    without_dates: bool,
+
+    /// Skip files smaller than the given size, like "100k" or "1.5M"
+    #[arg(long, value_name = "SIZE", value_parser = cli_tools::parse_size)]
+    min_size: Option<u64>,
+
+    /// Skip files larger than the given size, like "100k" or "1.5M"
+    #[arg(long, value_name = "SIZE", value_parser = cli_tools::parse_size)]
+    max_size: Option<u64>,
+
+    /// How long to wait for another tool to release a locked directory, like "30s" or "5m"
+    #[arg(long, value_name = "DURATION", value_parser = cli_tools::parse_duration)]
+    lock_wait: Option<Duration>,
+
+    /// Only process files listed in the given file, one path per line. Use '-' to read from stdin
+    #[arg(long, value_name = "FILE", conflicts_with = "recursive")]
+    files_from: Option<String>,
+
+    /// Do not fail if some files listed with --files-from do not exist
+    #[arg(long, requires = "files_from")]
+    ignore_missing: bool,
+
+    /// Slow down and retry renames, for network drives. Enabled automatically for network paths
+    #[arg(short, long)]
+    gentle: bool,
+
+    /// Only process the first N paths that would change
+    #[arg(short, long, value_name = "N", conflicts_with = "sample")]
+    limit: Option<usize>,
+
+    /// Only process a random sample of N paths that would change
+    #[arg(long, value_name = "N")]
+    sample: Option<usize>,
+
+    /// Seed for a reproducible random sample
+    #[arg(long, requires = "sample")]
+    seed: Option<u64>,
+
+    /// Approve, skip, or edit each rename interactively
+    #[arg(short, long, conflicts_with = "print")]
+    interactive: bool,
+
+    /// Select which paths to rename [default: both with --directory, otherwise files]
+    #[arg(short, long, value_enum)]
+    only: Option<RenameTarget>,
+
     /// Only print changes without renaming files
     #[arg(short, long)]
     print: bool,
@@ -97,6 +273,10 @@ struct Args {
     #[arg(short = 'b', long, conflicts_with = "prefix")]
     prefix_dir: bool,
 
+    /// Store the original name of renamed paths
+    #[arg(long, value_enum, value_name = "MODE")]
+    preserve_original: Option<PreserveMode>,
+
     /// Append suffix to the end
     #[arg(long)]
     suffix: Option<String>,
@@ -112,10 +292,186 @@ struct Args {
     /// Verbose output
     #[arg(short, long)]
     verbose: bool,
+
+    /// Check that each rename actually happened and fail if it did not
+    #[arg(long)]
+    verify: bool,
+
+    /// Flush directory changes to disk after renaming, for removable drives
+    #[arg(long)]
+    sync: bool,
+
+    /// Time formatting the given number of synthetic names
+    #[arg(long, hide = true, value_name = "COUNT")]
+    bench_internal: Option<usize>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Check file names against the naming policy from the config file without renaming
+    Check {
+        /// Optional input directories
+        paths: Vec<String>,
+    },
+    /// Print formatted names for file names read from stdin or a file without renaming anything
+    Format {
+        /// File with one name per line, or '-' to read from stdin
+        #[arg(default_value = "-")]
+        input: String,
+
+        /// Print the original and formatted name separated by a tab
+        #[arg(long)]
+        pairs: bool,
+
+        /// Directory name to use with --prefix-dir
+        #[arg(long, value_name = "NAME")]
+        dir_name: Option<String>,
+    },
+    /// Revert the renames of the most recent run
+    Undo {
+        /// Directory that was renamed
+        path: Option<String>,
+
+        /// Only print what would be reverted
+        #[arg(short, long)]
+        print: bool,
+    },
+    /// Print the stored original name of a renamed file or directory
+    Originals {
+        /// Renamed files or directories
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+}
+
+/// Where to store the original name of renamed paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum PreserveMode {
+    /// User extended attribute on the renamed path
+    Xattr,
+    /// Tab-separated manifest file in the parent directory
+    Manifest,
+}
+
+/// User answer for an interactive rename.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RenameDecision {
+    Apply,
+    Skip,
+    Edit(String),
+    ApplyAll,
+    Quit,
+}
+
+/// Progress of interactive renaming.
+#[derive(Debug, Default)]
+struct InteractiveState {
+    apply_all: bool,
+    quit: bool,
+    num_skipped: usize,
+    num_edited: usize,
+}
+
+/// Filter files by whether the name contains a date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DateFilter {
+    WithDates,
+    WithoutDates,
+}
+
+/// A single rename in the rename log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct RenameLogEntry {
+    old: PathBuf,
+    new: PathBuf,
+    /// Time of the rename in RFC 3339 format
+    timestamp: String,
+}
+
+/// Renames of the most recent runs in a directory, oldest run first.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RenameLog {
+    runs: Vec<Vec<RenameLogEntry>>,
+}
+
+/// Kind of token in a structured media name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum TokenClass {
+    /// Tokens that are not recognized as any other class
+    Title,
+    Year,
+    Episode,
+    Resolution,
+    Source,
+    Codec,
+}
+
+/// Which file to keep when removing duplicates.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DedupeKeep {
+    /// Keep the most recently modified file
+    #[default]
+    Newer,
+    /// Keep the least recently modified file
+    Older,
+}
+
+/// Which kind of paths to rename.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RenameTarget {
+    #[default]
+    Files,
+    Dirs,
+    Both,
+}
+
+/// Formatting rule for an extension or a comma-separated group of extensions from config file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExtensionRuleConfig {
+    #[serde(default)]
+    case: Option<bool>,
+    #[serde(default)]
+    cleanup: Option<bool>,
+    #[serde(default)]
+    prefix: Option<String>,
+    #[serde(default)]
+    suffix: Option<String>,
+    #[serde(default)]
+    replace: Vec<(String, String)>,
+    #[serde(default)]
+    regex_replace: Vec<(String, String)>,
+}
+
+/// Extensions allowed in directories matching the pattern.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExtensionPolicyConfig {
+    directory: String,
+    allowed: Vec<String>,
+}
+
+/// Naming policy from config file for the check subcommand.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NamingPolicyConfig {
+    #[serde(default)]
+    allowed_characters: Option<String>,
+    #[serde(default)]
+    max_length: Option<usize>,
+    #[serde(default)]
+    dot_separated: bool,
+    #[serde(default)]
+    no_spaces: bool,
+    #[serde(default)]
+    extensions: Vec<ExtensionPolicyConfig>,
 }
 
 /// Config from config file
 #[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
 struct DotsConfig {
     #[serde(default)]
     replace: Vec<(String, String)>,
@@ -126,26 +482,132 @@ struct DotsConfig {
     #[serde(default)]
     move_to_end: Vec<String>,
     #[serde(default)]
+    skip_names: Vec<String>,
+    #[serde(default)]
+    skip_extensions: Vec<String>,
+    #[serde(default)]
+    extensions: BTreeMap<String, ExtensionRuleConfig>,
+    #[serde(default)]
+    policy: Option<NamingPolicyConfig>,
+    #[serde(default)]
     prefix_dir: bool,
     #[serde(default)]
+    preserve_original: Option<PreserveMode>,
+    #[serde(default)]
+    check_order: bool,
+    #[serde(default)]
     debug: bool,
     #[serde(default)]
+    dedupe_normalization: bool,
+    #[serde(default)]
     dryrun: bool,
     #[serde(default)]
+    gentle: bool,
+    #[serde(default)]
+    gentle_delay_ms: Option<u64>,
+    #[serde(default)]
+    retries: Option<u32>,
+    #[serde(default)]
     overwrite: bool,
     #[serde(default)]
     directory: bool,
     #[serde(default)]
+    only: Option<RenameTarget>,
+    #[serde(default)]
     recursive: bool,
     #[serde(default)]
     verbose: bool,
+    #[serde(default)]
+    year_brackets: bool,
+    #[serde(default)]
+    reorder: bool,
+    #[serde(default)]
+    token_order: Option<Vec<String>>,
 }
 
-/// Wrapper needed to parse config section.
+/// Formatting rules from a `.dots.toml` file in the directory being processed or its parents.
 #[derive(Debug, Default, Deserialize)]
-struct UserConfig {
+#[serde(deny_unknown_fields)]
+struct LocalConfig {
+    #[serde(default)]
+    substitute: Vec<(String, String)>,
+    #[serde(default)]
+    remove: Vec<String>,
+    #[serde(default)]
+    regex: Vec<(String, String)>,
+    #[serde(default)]
+    prefix: Option<String>,
     #[serde(default)]
-    dots: DotsConfig,
+    suffix: Option<String>,
+}
+
+/// Formatting settings that a local config file extends, saved for restoring after the root is done.
+#[derive(Debug, Default)]
+struct GlobalRules {
+    replace: Vec<(String, String)>,
+    regex_replace: Vec<(Regex, String)>,
+    prefix: Option<String>,
+    suffix: Option<String>,
+}
+
+/// Formatting rule that overrides the global settings for files with matching extension.
+///
+/// Prefix and suffix templates can contain `{date}`,
+/// which is replaced with the modification date of the file.
+/// Replacements are applied after case conversion, so they can also fix the casing of name parts.
+#[derive(Debug, Default)]
+struct ExtensionRule {
+    /// Config key the rule was defined with
+    name: String,
+    case: Option<bool>,
+    cleanup: Option<bool>,
+    prefix: Option<String>,
+    suffix: Option<String>,
+    replace: Vec<(String, String)>,
+    regex_replace: Vec<(Regex, String)>,
+}
+
+/// Effective formatting settings for a single name.
+#[derive(Debug)]
+struct NameRule<'a> {
+    /// Name of the matching extension rule
+    extension_rule: Option<&'a str>,
+    convert_case: bool,
+    cleanup: bool,
+    prefix: Option<String>,
+    suffix: Option<String>,
+    replace: &'a [(String, String)],
+    regex_replace: &'a [(Regex, String)],
+}
+
+/// Naming policy rule that a file name can violate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum PolicyRule {
+    /// Name contains characters outside the allowed pattern
+    Characters,
+    /// Name is longer than the maximum length
+    Length,
+    /// Name contains whitespace
+    Spaces,
+    /// Name is not dot-separated words
+    Structure,
+    /// Extension is not allowed in the directory
+    Extension,
+}
+
+/// Compiled naming policy.
+#[derive(Debug, Default)]
+struct NamingPolicy {
+    /// Every character of the name must match this pattern
+    allowed_characters: Option<Regex>,
+    /// Maximum name length in characters, including the extension
+    max_length: Option<usize>,
+    /// Name must consist of non-empty parts separated by single dots
+    dot_separated: bool,
+    no_spaces: bool,
+    /// Allowed lowercase extensions for directories matching the pattern.
+    /// The first matching directory pattern is used.
+    extensions: Vec<(Regex, Vec<String>)>,
 }
 
 /// Final config created from CLI arguments and user config file.
@@ -155,30 +617,116 @@ struct Config {
     regex_replace: Vec<(Regex, String)>,
     move_to_start: Vec<String>,
     move_to_end: Vec<String>,
+    skip_rules: cli_tools::SkipRules,
+    /// Extension rules by lowercase extension
+    extension_rules: HashMap<String, ExtensionRule>,
     prefix: Option<String>,
     suffix: Option<String>,
+    files_from: Option<String>,
+    date_filter: Option<DateFilter>,
+    preserve_original: Option<PreserveMode>,
     prefix_dir: bool,
+    check_order: bool,
     convert_case: bool,
     debug: bool,
-    directory: bool,
+    dedupe: bool,
+    dedupe_keep: DedupeKeep,
+    dedupe_normalization: bool,
+    dedupe_verify: bool,
     dryrun: bool,
+    gentle: bool,
+    interactive: bool,
+    no_final_cleanup: bool,
+    no_local_rules: bool,
+    /// Maximum number of paths to process
+    limit: Option<usize>,
+    /// Number of randomly sampled paths to process
+    sample: Option<usize>,
+    /// Seed for the random sample
+    seed: Option<u64>,
+    /// Minimum file size in bytes
+    min_size: Option<u64>,
+    /// Maximum file size in bytes
+    max_size: Option<u64>,
+    /// How long to wait for a locked directory
+    lock_wait: Duration,
+    /// Delay between renames in gentle mode
+    gentle_delay: Duration,
+    /// Number of retries for temporary rename errors in gentle mode
+    retries: u32,
+    ignore_missing: bool,
     overwrite: bool,
     recursive: bool,
+    sync: bool,
+    target: RenameTarget,
     verbose: bool,
+    verify: bool,
+    year_brackets: bool,
+    /// Token order when reordering is enabled
+    token_order: Option<Vec<TokenClass>>,
 }
 
 #[derive(Debug, Default)]
 struct Dots {
+    /// All input paths in processing order
+    roots: Vec<PathBuf>,
+    /// Input path currently being processed
     root: PathBuf,
+    /// Lock for the directory currently being processed
+    root_lock: Option<cli_tools::lock::DirLock>,
     config: Config,
+    num_missing: usize,
+    /// Paths that could not be renamed with the error message
+    failed: Vec<(PathBuf, String)>,
+    /// Renames that reported success but could not be verified
+    unverified: Vec<(PathBuf, PathBuf)>,
+    /// Duplicate files moved to trash with the file that was kept
+    deduplicated: Vec<(PathBuf, PathBuf)>,
+    /// Renames done in the current root for the rename log
+    journal: Vec<RenameLogEntry>,
+    interactive: InteractiveState,
+    /// Number of files with and without a date when using a date filter
+    date_counts: Option<(usize, usize)>,
+    /// Number of files skipped by the size filters
+    num_size_filtered: usize,
+    /// Number of paths selected with a limit or sample size
+    num_selected: usize,
+    /// Number of paths that would change but were left out by the limit or sample size
+    num_limited: usize,
 }
 
 impl Dots {
     /// Init new instance with CLI args.
     pub fn new(args: Args) -> Result<Self> {
-        let root = cli_tools::resolve_input_path(args.path.as_deref())?;
+        let paths = cli_tools::resolve_input_paths(&args.paths)?;
         let config = Config::from_args(args)?;
-        Ok(Self { root, config })
+        Self::with_config(&paths, config)
+    }
+
+    /// Init new instance for the given input paths with a resolved config.
+    fn with_config(paths: &[PathBuf], config: Config) -> Result<Self> {
+        // Recursive mode can also come from the user config
+        let roots = dedupe_roots(paths, config.recursive);
+        if roots.len() > 1 && config.files_from.is_some() {
+            anyhow::bail!("--files-from can only be used with a single input path");
+        }
+        let root = roots.first().cloned().unwrap_or_default();
+        Ok(Self {
+            roots,
+            root,
+            root_lock: None,
+            config,
+            num_missing: 0,
+            failed: Vec::new(),
+            unverified: Vec::new(),
+            deduplicated: Vec::new(),
+            journal: Vec::new(),
+            interactive: InteractiveState::default(),
+            date_counts: None,
+            num_size_filtered: 0,
+            num_selected: 0,
+            num_limited: 0,
+        })
     }
 
     pub fn run_with_args(args: Args) -> Result<()> {
@@ -191,36 +739,290 @@ impl Dots {
             println!("{self}");
         }
 
-        let (paths_to_rename, name) = if self.config.directory {
-            (self.gather_directories_to_rename(), "directories")
+        if self.config.interactive && !io::stdin().is_terminal() {
+            anyhow::bail!("Interactive mode requires a terminal");
+        }
+
+        let roots = if self.roots.is_empty() {
+            vec![self.root.clone()]
         } else {
-            (self.gather_files_to_rename()?, "files")
+            self.roots.clone()
         };
 
-        if paths_to_rename.is_empty() {
+        if !self.config.gentle && roots.iter().any(|root| cli_tools::is_network_path(root)) {
             if self.config.verbose {
-                println!("No {name} to rename");
+                println!("Network drive detected, using gentle mode");
             }
-            return Ok(());
+            self.config.gentle = true;
         }
 
-        let num_renamed = self.rename_paths(paths_to_rename);
-        let message = format!(
-            "{num_renamed} {}",
-            match (self.config.directory, num_renamed > 1) {
-                (true, true) => "directories",
-                (true, false) => "directory",
-                (false, true) => "files",
-                (false, false) => "file",
+        let mut num_files: usize = 0;
+        let mut num_directories: usize = 0;
+        let mut found_paths = false;
+        let mut date_counts: Option<(usize, usize)> = None;
+        let mut num_size_filtered: usize = 0;
+
+        let multiple_roots = roots.len() > 1;
+        for root in roots {
+            if self.interactive.quit {
+                break;
             }
-        );
+            if multiple_roots {
+                println!("{}", format!("Root: {}", root.display()).bold().underline());
+            }
+            self.root = root;
+            let global_rules = if self.config.no_local_rules {
+                None
+            } else {
+                self.apply_local_config()?
+            };
+            if !self.config.dryrun && !self.lock_root()? {
+                if let Some(rules) = global_rules {
+                    self.config.restore_rules(rules);
+                }
+                continue;
+            }
+            let result = self.rename_root();
+            self.root_lock = None;
+            self.write_rename_log();
+            if let Some(rules) = global_rules {
+                self.config.restore_rules(rules);
+            }
+            let Some((files, directories, found)) = result? else {
+                // Still report what happened in the earlier roots
+                self.print_deduplicated();
+                self.check_results()?;
+                anyhow::bail!("Aborted renaming because of sort order changes");
+            };
+            num_files += files;
+            num_directories += directories;
+            found_paths |= found;
+            if let Some((with_dates, without_dates)) = self.date_counts.take() {
+                let (total_with, total_without) = date_counts.get_or_insert((0, 0));
+                *total_with += with_dates;
+                *total_without += without_dates;
+            }
+            num_size_filtered += std::mem::take(&mut self.num_size_filtered);
+        }
+
+        if let Some((with_dates, without_dates)) = date_counts {
+            println!("Files with dates: {with_dates}, without dates: {without_dates}");
+        }
+        if self.config.has_size_filter() {
+            println!("Size filtered: {}", format_count(num_size_filtered, "file", "files"));
+        }
+
+        if !found_paths {
+            if self.config.verbose {
+                println!("No {} to rename", self.config.target.description());
+            }
+            return self.check_missing();
+        }
+
+        let files_message = format_count(num_files, "file", "files");
+        let directories_message = format_count(num_directories, "directory", "directories");
+        let message = match self.config.target {
+            RenameTarget::Files => files_message,
+            RenameTarget::Dirs => directories_message,
+            RenameTarget::Both => {
+                if num_directories == 0 {
+                    files_message
+                } else if num_files == 0 {
+                    directories_message
+                } else {
+                    format!("{files_message} and {directories_message}")
+                }
+            }
+        };
 
         if self.config.dryrun {
             println!("Dryrun: would have renamed {message}");
         } else {
             println!("{}", format!("Renamed {message}").green());
         }
-        Ok(())
+        if self.config.interactive {
+            println!(
+                "Skipped {}, edited {}",
+                format_count(self.interactive.num_skipped, "rename", "renames"),
+                format_count(self.interactive.num_edited, "name", "names")
+            );
+        }
+        if self.num_limited > 0 {
+            println!(
+                "Skipped {} over the limit",
+                format_count(self.num_limited, "eligible path", "eligible paths")
+            );
+        }
+        self.print_deduplicated();
+        self.check_results()
+    }
+
+    /// Report failed, unverified, and missing paths and fail if there were any.
+    fn check_results(&self) -> Result<()> {
+        let failed = self.check_failed();
+        let unverified = self.check_unverified();
+        let missing = self.check_missing();
+        failed.and(unverified).and(missing)
+    }
+
+    /// Report renames that could not be verified and fail if there were any.
+    fn check_unverified(&self) -> Result<()> {
+        if self.unverified.is_empty() {
+            return Ok(());
+        }
+        eprintln!("{}", "Renames that could not be verified:".red().bold());
+        for (path, new_path) in &self.unverified {
+            eprintln!(
+                "{}",
+                format!(
+                    "  {} -> {}",
+                    cli_tools::get_relative_path_or_filename(path, &self.root),
+                    cli_tools::get_relative_path_or_filename(new_path, &self.root)
+                )
+                .red()
+            );
+        }
+        Err(anyhow!(
+            "Failed to verify {}",
+            format_count(self.unverified.len(), "rename", "renames")
+        ))
+    }
+
+    /// Lock the current root directory so other tools do not modify it at the same time.
+    /// Returns false if another tool kept the directory locked for the whole wait time.
+    fn lock_root(&mut self) -> Result<bool> {
+        let dir = if self.root.is_dir() {
+            self.root.clone()
+        } else {
+            self.root.parent().context("Failed to get parent dir")?.to_path_buf()
+        };
+        self.root_lock = cli_tools::lock::acquire_dir_lock(&dir, "dots", self.config.lock_wait)?;
+        if self.root_lock.is_none() {
+            let holder = cli_tools::lock::read_dir_lock(&dir)
+                .map(|info| format!(" by {} (pid {})", info.tool, info.pid))
+                .unwrap_or_default();
+            eprintln!(
+                "{}",
+                format!("Skipping directory locked{holder}: {}", dir.display()).yellow()
+            );
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Rename files and directories under the current root.
+    ///
+    /// Returns the number of renamed files and directories, and whether there were any paths to rename,
+    /// or `None` if the user aborted.
+    fn rename_root(&mut self) -> Result<Option<(usize, usize, bool)>> {
+        let mut num_files: usize = 0;
+        let mut num_directories: usize = 0;
+        let mut found_paths = false;
+
+        // Rename files first so that the gathered file paths stay valid
+        if self.config.target.includes_files() {
+            let files = self.gather_files_to_rename()?;
+            let files = self.select_paths(files);
+            if self.config.check_order && !self.check_sort_order(&files) {
+                return Ok(None);
+            }
+            found_paths |= !files.is_empty();
+            num_files = self.rename_paths(files);
+        }
+        if self.config.target.includes_directories() && !self.interactive.quit {
+            let directories = self.gather_directories_to_rename();
+            let directories = self.select_paths(directories);
+            found_paths |= !directories.is_empty();
+            num_directories = self.rename_paths(directories);
+        }
+        Ok(Some((num_files, num_directories, found_paths)))
+    }
+
+    /// Time formatting synthetic names to measure the performance of the format pipeline.
+    fn benchmark(&self, count: usize) {
+        let names: Vec<String> = (0..count)
+            .map(|index| {
+                format!(
+                    "Some Artist - Song_Name {index} (Official Video) [1080p] www.example.com {:013x}",
+                    index * 7919
+                )
+            })
+            .collect();
+        let start = Instant::now();
+        let total_length: usize = names.iter().map(|name| self.format_name(name).len()).sum();
+        let elapsed = start.elapsed();
+        println!(
+            "Formatted {count} names in {elapsed:.2?} ({:.2?} per name, {total_length} characters)",
+            elapsed
+                .checked_div(u32::try_from(count.max(1)).unwrap_or(u32::MAX))
+                .unwrap_or_default()
+        );
+    }
+
+    /// Print duplicates that were moved to trash and the files they duplicated.
+    fn print_deduplicated(&self) {
+        if self.deduplicated.is_empty() {
+            return;
+        }
+        println!(
+            "{}",
+            format!(
+                "Moved {} to trash:",
+                format_count(self.deduplicated.len(), "duplicate file", "duplicate files")
+            )
+            .bold()
+        );
+        for (trashed, kept) in &self.deduplicated {
+            println!(
+                "  {} (duplicate of {})",
+                cli_tools::get_relative_path_or_filename(trashed, &self.root),
+                cli_tools::get_relative_path_or_filename(kept, &self.root)
+            );
+        }
+    }
+
+    /// Report paths that could not be renamed and fail if there were any.
+    fn check_failed(&self) -> Result<()> {
+        if self.failed.is_empty() {
+            return Ok(());
+        }
+        eprintln!(
+            "{}",
+            format!("Failed to rename {}:", format_count(self.failed.len(), "path", "paths"))
+                .red()
+                .bold()
+        );
+        for (path, error) in &self.failed {
+            eprintln!(
+                "{}",
+                format!(
+                    "  {}: {error}",
+                    cli_tools::get_relative_path_or_filename(path, &self.root)
+                )
+                .red()
+            );
+        }
+        Err(anyhow!(
+            "Failed to rename {}",
+            format_count(self.failed.len(), "path", "paths")
+        ))
+    }
+
+    /// Report listed files that were not found and fail unless missing files are ignored.
+    fn check_missing(&self) -> Result<()> {
+        if self.num_missing == 0 {
+            return Ok(());
+        }
+        let message = format!(
+            "Missing {} from the file list",
+            format_count(self.num_missing, "file", "files")
+        );
+        if self.config.ignore_missing {
+            println!("{}", message.yellow());
+            Ok(())
+        } else {
+            Err(anyhow!(message))
+        }
     }
 
     /// Get all files that need to be renamed.
@@ -239,7 +1041,7 @@ impl Dots {
             self.config.prefix = Option::from(prefix);
         }
 
-        if self.root.is_file() {
+        if self.config.files_from.is_none() && self.root.is_file() {
             if self.config.verbose {
                 println!("{}", format!("Formatting file {}", self.root.display()).bold());
             }
@@ -251,67 +1053,341 @@ impl Dots {
                 .unwrap_or_default());
         }
 
-        if self.config.verbose {
-            println!("{}", format!("Formatting files under {}", self.root.display()).bold());
-        }
+        let files = if let Some(source) = self.config.files_from.clone() {
+            self.read_files_from(&source)?
+        } else {
+            if self.config.verbose {
+                println!("{}", format!("Formatting files under {}", self.root.display()).bold());
+            }
 
-        let max_depth = if self.config.recursive { 100 } else { 1 };
+            let max_depth = if self.config.recursive { 100 } else { 1 };
+            let mut num_size_filtered: usize = 0;
+
+            let files = WalkDir::new(&self.root)
+                .max_depth(max_depth)
+                .into_iter()
+                // ignore hidden files, system directories, and user configured names
+                .filter_entry(|e| !self.should_skip(e))
+                .filter_map(Result::ok)
+                .filter(|entry| {
+                    let keep = self.within_size_limits(entry);
+                    if !keep {
+                        num_size_filtered += 1;
+                    }
+                    keep
+                })
+                .map(walkdir::DirEntry::into_path)
+                .filter(|path| path.is_file())
+                .collect();
+
+            self.num_size_filtered = num_size_filtered;
+            files
+        };
+
+        let files = if let Some(filter) = self.config.date_filter {
+            self.filter_by_date(files, filter)
+        } else {
+            files
+        };
+
+        let mut conflicting: HashSet<PathBuf> = HashSet::new();
+        let mut resolved: Vec<(PathBuf, PathBuf)> = Vec::new();
+        for (keep, others) in find_normalization_conflicts(&files) {
+            if self.config.dedupe_normalization {
+                // Keep the NFC file and move the other variants out of the way with an NFC name
+                for path in others {
+                    let (file_name, file_extension) = cli_tools::get_normalized_file_name_and_extension(&path)?;
+                    let normalized = if file_extension.is_empty() {
+                        path.with_file_name(file_name)
+                    } else {
+                        path.with_file_name(format!("{file_name}.{file_extension}"))
+                    };
+                    let new_path = cli_tools::insert_suffix_before_extension(&normalized, ".nfd");
+                    conflicting.insert(path.clone());
+                    resolved.push((path, new_path));
+                }
+            } else {
+                let names = std::iter::once(&keep)
+                    .chain(others.iter())
+                    .map(|path| format!("{:?}", cli_tools::get_relative_path_or_filename(path, &self.root)))
+                    .join(", ");
+                eprintln!(
+                    "{}",
+                    format!("Skipping files that differ only by unicode normalization: {names}").yellow()
+                );
+                conflicting.insert(keep);
+                conflicting.extend(others);
+            }
+        }
 
         // Collect and sort all files that need renaming
-        Ok(WalkDir::new(&self.root)
-            .max_depth(max_depth)
+        Ok(files
             .into_iter()
-            // ignore hidden files (name starting with ".")
-            .filter_entry(|e| !cli_tools::is_hidden(e))
-            .filter_map(Result::ok)
-            .filter_map(|entry| {
-                let path = entry.path();
-                self.formatted_filepath(path)
+            .filter(|path| !conflicting.contains(path))
+            .filter_map(|path| {
+                self.formatted_filepath(&path)
                     .ok()
-                    .filter(|new_path| path != new_path)
-                    .map(|new_path| (path.to_path_buf(), new_path))
+                    .filter(|new_path| &path != new_path)
+                    .map(|new_path| (path, new_path))
             })
+            .chain(resolved)
             .sorted_by_key(|(path, _)| path.to_string_lossy().to_lowercase())
             .collect())
     }
 
+    /// Select the paths to process when a limit or sample size is set.
+    /// The limit is shared between files and directories,
+    /// and paths that are left out are counted as limited.
+    fn select_paths(&mut self, paths: Vec<(PathBuf, PathBuf)>) -> Vec<(PathBuf, PathBuf)> {
+        let Some(count) = self.config.limit.or(self.config.sample) else {
+            return paths;
+        };
+        let remaining = count.saturating_sub(self.num_selected);
+        if paths.len() <= remaining {
+            self.num_selected += paths.len();
+            return paths;
+        }
+        self.num_selected += remaining;
+        self.num_limited += paths.len() - remaining;
+        if self.config.sample.is_some() {
+            let mut rng = self
+                .config
+                .seed
+                .map_or_else(fastrand::Rng::new, fastrand::Rng::with_seed);
+            // Keep the selected paths in their original order
+            let mut indices = rng.choose_multiple(0..paths.len(), remaining);
+            indices.sort_unstable();
+            let mut indices = indices.into_iter().peekable();
+            paths
+                .into_iter()
+                .enumerate()
+                .filter_map(|(index, pair)| indices.next_if_eq(&index).map(|_| pair))
+                .collect()
+        } else {
+            paths.into_iter().take(remaining).collect()
+        }
+    }
+
+    /// Check if the walk entry should be skipped.
+    /// Skipped directories are pruned so their contents are never visited.
+    /// The root itself is never skipped.
+    fn should_skip(&self, entry: &walkdir::DirEntry) -> bool {
+        let skip = entry.depth() > 0 && self.config.skip_rules.should_skip(entry);
+        if skip && self.config.verbose && entry.file_type().is_dir() {
+            println!(
+                "Skipping directory: {}",
+                cli_tools::get_relative_path_or_filename(entry.path(), &self.root)
+            );
+        }
+        skip
+    }
+
+    /// Check if the file size is within the configured limits.
+    /// Directories always pass, and files whose metadata can not be read are skipped with a warning.
+    fn within_size_limits(&self, entry: &walkdir::DirEntry) -> bool {
+        if !self.config.has_size_filter() || entry.file_type().is_dir() {
+            return true;
+        }
+        let metadata = if entry.path_is_symlink() {
+            fs::metadata(entry.path())
+        } else {
+            entry.metadata().map_err(io::Error::from)
+        };
+        match metadata {
+            Ok(metadata) if metadata.is_dir() => true,
+            Ok(metadata) => {
+                let size = metadata.len();
+                self.config.min_size.is_none_or(|min| size >= min) && self.config.max_size.is_none_or(|max| size <= max)
+            }
+            Err(error) => {
+                eprintln!(
+                    "{}",
+                    format!(
+                        "Skipping file, failed to read metadata: {}: {error}",
+                        cli_tools::get_relative_path_or_filename(entry.path(), &self.root)
+                    )
+                    .yellow()
+                );
+                false
+            }
+        }
+    }
+
+    /// Only keep files with or without a date in the file name, and count both groups.
+    fn filter_by_date(&mut self, files: Vec<PathBuf>, filter: DateFilter) -> Vec<PathBuf> {
+        let (with_dates, without_dates): (Vec<PathBuf>, Vec<PathBuf>) = files.into_iter().partition(|path| {
+            path.file_stem()
+                .is_some_and(|stem| cli_tools::date::contains_date(&cli_tools::os_str_to_string(stem)))
+        });
+        self.date_counts = Some((with_dates.len(), without_dates.len()));
+        match filter {
+            DateFilter::WithDates => with_dates,
+            DateFilter::WithoutDates => without_dates,
+        }
+    }
+
+    /// Read the list of files to process from a file or stdin.
+    /// Paths that do not exist are printed with their line numbers and counted as missing.
+    fn read_files_from(&mut self, source: &str) -> Result<Vec<PathBuf>> {
+        let content = if source == "-" {
+            io::read_to_string(io::stdin()).context("Failed to read file list from stdin")?
+        } else {
+            fs::read_to_string(source).with_context(|| format!("Failed to read file list: {source}"))?
+        };
+        let root = if self.root.is_file() {
+            self.root.parent().context("Failed to get parent dir")?
+        } else {
+            &self.root
+        };
+        if self.config.verbose {
+            println!("{}", format!("Formatting files listed in {source}").bold());
+        }
+
+        let (files, missing) = parse_file_list(&content, root);
+        for (line_number, line) in &missing {
+            eprintln!("{}", format!("Line {line_number}: file not found: {line}").red());
+        }
+        self.num_missing = missing.len();
+
+        // Listed files still need to pass the same filters as walked files
+        let mut num_size_filtered: usize = 0;
+        let files = files
+            .into_iter()
+            .filter(|path| !self.is_listed_file_skipped(root, path))
+            .filter(|path| {
+                let keep = path_entry(path).is_some_and(|entry| self.within_size_limits(&entry));
+                if !keep {
+                    num_size_filtered += 1;
+                }
+                keep
+            })
+            .collect();
+        self.num_size_filtered = num_size_filtered;
+        Ok(files)
+    }
+
+    /// Check if a listed file would be skipped when walking the root directory.
+    /// The file and the directories between it and the root are checked with the skip rules.
+    fn is_listed_file_skipped(&self, root: &Path, path: &Path) -> bool {
+        path.ancestors()
+            .take_while(|ancestor| *ancestor == path || (ancestor.starts_with(root) && *ancestor != root))
+            .filter_map(path_entry)
+            .any(|entry| self.config.skip_rules.should_skip(&entry))
+    }
+
     /// Get all directories that need to be renamed.
     fn gather_directories_to_rename(&self) -> Vec<(PathBuf, PathBuf)> {
         let max_depth = if self.config.recursive { 100 } else { 1 };
         WalkDir::new(&self.root)
             .max_depth(max_depth)
             .into_iter()
+            .filter_entry(|e| !self.should_skip(e))
             .filter_map(Result::ok)
             .filter(|entry| entry.path().is_dir())
             .filter_map(|entry| {
                 let path = entry.path();
-                    self.formatted_directory_path(path)
-                        .ok()
-                        .filter(|new_path| path != new_path)
-                        .map(|new_path| (path.to_path_buf(), new_path))
+                self.formatted_directory_path(path)
+                    .ok()
+                    .filter(|new_path| path != new_path)
+                    .map(|new_path| (path.to_path_buf(), new_path))
             })
             // Sort by depth to rename children before parents, avoiding renaming conflicts
             .sorted_by_key(|(path, _)| std::cmp::Reverse(path.components().count()))
             .collect()
     }
 
+    /// Warn about renames that would change the sort order of files in their directory.
+    /// Returns false if the user chose not to continue.
+    fn check_sort_order(&self, renames: &[(PathBuf, PathBuf)]) -> bool {
+        let changes = find_sort_order_changes(renames);
+        if changes.is_empty() {
+            return true;
+        }
+        println!(
+            "{}",
+            format!(
+                "Renaming would change the sort order of {}:",
+                format_count(changes.len(), "file", "files")
+            )
+            .yellow()
+            .bold()
+        );
+        for path in &changes {
+            println!("  {}", cli_tools::get_relative_path_or_filename(path, &self.root));
+        }
+        self.config.dryrun || confirm("Continue renaming?")
+    }
+
     /// Rename all given path pairs or just print changes if dryrun is enabled.
-    fn rename_paths(&self, paths: Vec<(PathBuf, PathBuf)>) -> usize {
+    /// Paths that fail to rename are recorded and skipped.
+    /// In gentle mode, waits between renames and retries temporary errors.
+    fn rename_paths(&mut self, paths: Vec<(PathBuf, PathBuf)>) -> usize {
+        let stdin = io::stdin();
+        if self.config.dryrun {
+            self.rename_paths_with_input(paths, &mut stdin.lock(), &DryRunFs::new())
+        } else {
+            self.rename_paths_with_input(paths, &mut stdin.lock(), &RealFs)
+        }
+    }
+
+    /// Rename path pairs with the given file system operations,
+    /// reading the answers for interactive mode from the given input.
+    fn rename_paths_with_input(
+        &mut self,
+        mut paths: Vec<(PathBuf, PathBuf)>,
+        input: &mut impl BufRead,
+        fs_ops: &impl FsOps,
+    ) -> usize {
         let mut num_renamed: usize = 0;
+        let mut changed_directories: BTreeSet<PathBuf> = BTreeSet::new();
         let max_items = paths.len();
         let max_chars = paths.len().to_string().chars().count();
-        for (index, (path, new_path)) in paths.into_iter().enumerate() {
+        for index in 0..paths.len() {
+            let (path, mut new_path) = paths[index].clone();
             let old_str = cli_tools::get_relative_path_or_filename(&path, &self.root);
-            let new_str = cli_tools::get_relative_path_or_filename(&new_path, &self.root);
+            let mut new_str = cli_tools::get_relative_path_or_filename(&new_path, &self.root);
             let number = format!("{:>max_chars$} / {max_items}", index + 1);
 
             if self.config.dryrun {
                 println!("{}", format!("Dryrun {number}:").bold().cyan());
-                cli_tools::show_diff(&old_str, &new_str);
-                num_renamed += 1;
+                cli_tools::show_path_diff(&old_str, &new_str);
+                match fs_ops.rename(&path, &new_path) {
+                    Ok(()) => num_renamed += 1,
+                    Err(e) => self.failed.push((path, format!("{e:#}"))),
+                }
                 continue;
             }
 
+            let mut show_rename = true;
+            let mut edited = false;
+            if self.config.interactive && !self.interactive.apply_all {
+                println!("{}", format!("Rename {number}:").bold().magenta());
+                cli_tools::show_path_diff(&old_str, &new_str);
+                show_rename = false;
+                match ask_rename_decision(input) {
+                    RenameDecision::Apply => {}
+                    RenameDecision::ApplyAll => self.interactive.apply_all = true,
+                    RenameDecision::Edit(name) => {
+                        new_path = path.with_file_name(name);
+                        new_str = cli_tools::get_relative_path_or_filename(&new_path, &self.root);
+                        edited = true;
+                        show_rename = true;
+                    }
+                    RenameDecision::Skip => {
+                        self.interactive.num_skipped += 1;
+                        continue;
+                    }
+                    RenameDecision::Quit => {
+                        self.interactive.quit = true;
+                        break;
+                    }
+                }
+                if new_path == path {
+                    self.interactive.num_skipped += 1;
+                    continue;
+                }
+            }
+
             let capitalization_change_only = if new_str.to_lowercase() == old_str.to_lowercase() {
                 // File path contains only capitalisation changes:
                 // Need to use a temp file to workaround case-insensitive file systems.
@@ -319,48 +1395,264 @@ impl Dots {
             } else {
                 false
             };
-            if !capitalization_change_only && new_path.exists() && !self.config.overwrite {
-                println!(
-                    "{}",
-                    format!("Skipping rename to already existing file: {new_str}").yellow()
-                );
-                continue;
+            let target_exists = if capitalization_change_only {
+                // Another file with exactly the new name on a case-sensitive file system
+                is_listed_in_parent(&new_path) && is_listed_in_parent(&path)
+            } else {
+                new_path.exists()
+            };
+            if target_exists && !self.config.overwrite {
+                if self.config.dedupe {
+                    match self.trash_duplicate(&path, &new_path, fs_ops) {
+                        // Kept the existing file so there is nothing left to rename
+                        Ok(Some(trashed)) if trashed == path => continue,
+                        Ok(Some(_)) => {}
+                        Ok(None) => {
+                            println!(
+                                "{}",
+                                format!("Skipping rename to already existing different file: {new_str}").yellow()
+                            );
+                            self.interactive.num_skipped += usize::from(edited);
+                            continue;
+                        }
+                        Err(e) => {
+                            self.failed.push((path, format!("{e:#}")));
+                            continue;
+                        }
+                    }
+                } else {
+                    println!(
+                        "{}",
+                        format!("Skipping rename to already existing file: {new_str}").yellow()
+                    );
+                    self.interactive.num_skipped += usize::from(edited);
+                    continue;
+                }
+            }
+            // Only count edited names that are actually used
+            self.interactive.num_edited += usize::from(edited);
+
+            if show_rename {
+                println!("{}", format!("Rename {number}:").bold().magenta());
+                cli_tools::show_path_diff(&old_str, &new_str);
             }
 
-            println!("{}", format!("Rename {number}:").bold().magenta());
-            cli_tools::show_diff(&old_str, &new_str);
+            if let Some(lock) = self.root_lock.as_mut() {
+                lock.keep_alive();
+            }
 
-            let rename_result = if capitalization_change_only {
-                Self::rename_with_temp_file(&path, &new_path)
+            let rename = || {
+                let result = if capitalization_change_only {
+                    Self::rename_with_temp_file(fs_ops, &path, &new_path)
+                } else {
+                    fs_ops.rename(&path, &new_path)
+                };
+                // Keep the IO error so transient errors can be retried
+                result.map_err(|error| error.downcast::<io::Error>().unwrap_or_else(io::Error::other))
+            };
+            let rename_result = if self.config.gentle {
+                if index > 0 {
+                    thread::sleep(self.config.gentle_delay);
+                }
+                cli_tools::retry_transient(
+                    self.config.retries,
+                    self.config.gentle_delay,
+                    rename,
+                    |attempt, e, delay| {
+                        if self.config.verbose {
+                            println!(
+                                "{}",
+                                format!("Retry {attempt} in {} ms: {e}", delay.as_millis()).yellow()
+                            );
+                        }
+                    },
+                )
             } else {
-                fs::rename(&path, &new_path)
+                rename()
             };
 
             match rename_result {
                 Ok(()) => {
+                    changed_directories.extend(path.parent().map(Path::to_path_buf));
+                    changed_directories.extend(new_path.parent().map(Path::to_path_buf));
+                    let verified = !self.config.verify || verify_rename(&path, &new_path, capitalization_change_only);
+                    let moved = verified || is_moved_from(&path, &new_path, capitalization_change_only);
+                    if moved && new_path.is_dir() {
+                        // Pending renames under the renamed directory need to use the new parent path
+                        Self::rebase_pending_paths(&mut paths[index + 1..], &path, &new_path);
+                        if let Some(lock) = self.root_lock.as_mut().filter(|lock| lock.dir() == path) {
+                            lock.moved_to(&new_path);
+                        }
+                    }
+                    if !verified {
+                        eprintln!("{}", format!("Rename could not be verified: {old_str}").red());
+                        self.unverified.push((path, new_path));
+                        continue;
+                    }
                     num_renamed += 1;
+                    self.journal.push(RenameLogEntry {
+                        old: std::path::absolute(&path).unwrap_or_else(|_| path.clone()),
+                        new: std::path::absolute(&new_path).unwrap_or_else(|_| new_path.clone()),
+                        timestamp: chrono::Local::now().to_rfc3339(),
+                    });
+                    if let Some(mode) = self.config.preserve_original {
+                        if let Err(e) = store_original_name(mode, &path, &new_path) {
+                            eprintln!("{}", format!("Failed to store original name: {old_str}\n{e}").red());
+                        }
+                    }
                 }
                 Err(e) => {
                     eprintln!("{}", format!("Error renaming: {old_str}\n{e}").red());
+                    self.failed.push((path, e.to_string()));
+                }
+            }
+        }
+        if self.config.sync {
+            for dir in &changed_directories {
+                if let Err(e) = sync_directory(dir) {
+                    eprintln!(
+                        "{}",
+                        format!("Failed to sync directory {}: {e}", dir.display()).yellow()
+                    );
                 }
             }
         }
         num_renamed
     }
 
+    /// Add the rules from the nearest `.dots.toml` for the current root.
+    /// Returns the previous rules to restore after the root is done, or `None` if there is no local config.
+    fn apply_local_config(&mut self) -> Result<Option<GlobalRules>> {
+        let dir = if self.root.is_file() {
+            self.root.parent().unwrap_or(&self.root)
+        } else {
+            &self.root
+        };
+        let Some(path) = find_local_config(dir) else {
+            return Ok(None);
+        };
+        let local = LocalConfig::load(&path)?;
+        if self.config.verbose {
+            println!("Using local config: {}", path.display());
+        }
+        Ok(Some(self.config.merge_local(local)))
+    }
+
+    /// Append the renames done in the current root to the rename log in the root directory.
+    fn write_rename_log(&mut self) {
+        if self.journal.is_empty() {
+            return;
+        }
+        let entries = std::mem::take(&mut self.journal);
+        let root = std::path::absolute(&self.root).unwrap_or_else(|_| self.root.clone());
+        // The root itself might have been renamed
+        let root = entries
+            .iter()
+            .find(|entry| entry.old == root)
+            .map_or(root, |entry| entry.new.clone());
+        let root_dir = if root.is_file() {
+            root.parent().map(Path::to_path_buf).unwrap_or_default()
+        } else {
+            root
+        };
+        if let Err(e) = append_rename_log(&root_dir, entries) {
+            eprintln!("{}", format!("Failed to write rename log: {e:#}").red());
+        }
+    }
+
+    /// Move the file or the existing file with the new name to trash if they are duplicates.
+    /// Returns the path that was moved to trash, or `None` if the files differ.
+    fn trash_duplicate(&mut self, path: &Path, existing: &Path, fs_ops: &impl FsOps) -> Result<Option<PathBuf>> {
+        let Some(trashed) =
+            select_duplicate_to_trash(path, existing, self.config.dedupe_keep, self.config.dedupe_verify)?
+        else {
+            return Ok(None);
+        };
+        fs_ops.trash(&trashed)?;
+        let kept = if trashed == path { existing } else { path };
+        self.deduplicated.push((trashed.clone(), kept.to_path_buf()));
+        Ok(Some(trashed))
+    }
+
+    /// Update pending path pairs located under a renamed directory to use the new directory path.
+    fn rebase_pending_paths(pending: &mut [(PathBuf, PathBuf)], old_dir: &Path, new_dir: &Path) {
+        for (path, new_path) in pending {
+            for pending_path in [path, new_path] {
+                if pending_path.as_path() != old_dir {
+                    if let Ok(relative) = pending_path.strip_prefix(old_dir) {
+                        *pending_path = new_dir.join(relative);
+                    }
+                }
+            }
+        }
+    }
+
     /// Get the full path with formatted filename and extension.
     fn formatted_filepath(&self, path: &Path) -> Result<PathBuf> {
         if !path.is_file() {
             anyhow::bail!("Path is not a file")
         }
+        Ok(path.with_file_name(self.format_file_name(path)?))
+    }
 
-        if let Ok((file_name, file_extension)) = cli_tools::get_normalized_file_name_and_extension(path) {
-            let new_file = format!("{}.{}", self.format_name(&file_name), file_extension.to_lowercase());
-            let new_path = path.with_file_name(new_file);
-            Ok(new_path)
+    /// Format the file name with extension of the path.
+    /// Only reads the file metadata when a prefix or suffix template contains a date.
+    fn format_file_name(&self, path: &Path) -> Result<String> {
+        let Ok((file_name, file_extension)) = cli_tools::get_normalized_file_name_and_extension(path) else {
+            anyhow::bail!("Failed to get filename")
+        };
+        let file_extension = file_extension.to_lowercase();
+        let rule = self.file_name_rule(path, &file_extension);
+        if self.config.debug {
+            if let Some(name) = rule.extension_rule {
+                println!(
+                    "{}: extension rule [{name}] case: {}, cleanup: {}, prefix: {:?}, suffix: {:?}",
+                    cli_tools::get_relative_path_or_filename(path, &self.root),
+                    rule.convert_case,
+                    rule.cleanup,
+                    rule.prefix.as_deref().unwrap_or_default(),
+                    rule.suffix.as_deref().unwrap_or_default(),
+                );
+            }
+        }
+        let new_name = self.format_name_with_rule(&file_name, &rule);
+        if file_extension.is_empty() {
+            Ok(new_name)
         } else {
-            Err(anyhow!("Failed to get filename"))
+            Ok(format!("{new_name}.{file_extension}"))
+        }
+    }
+
+    /// Write the formatted name for each line of the input without touching the file system.
+    /// Directory components of the lines are kept as is and only the final name is formatted.
+    fn format_names(
+        &mut self,
+        content: &str,
+        output: &mut impl Write,
+        pairs: bool,
+        dir_name: Option<&str>,
+    ) -> Result<()> {
+        if self.config.prefix_dir {
+            let dir_name = dir_name.context("--prefix-dir needs --dir-name when formatting names from input")?;
+            self.config.prefix = Some(self.format_name(&dir_name.nfc().collect::<String>()));
+        }
+        for line in content.lines() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() {
+                continue;
+            }
+            let path = Path::new(line);
+            let formatted = self.format_file_name(path).map_or_else(
+                |_| line.to_string(),
+                |name| path.with_file_name(name).to_string_lossy().into_owned(),
+            );
+            if pairs {
+                writeln!(output, "{line}\t{formatted}")?;
+            } else {
+                writeln!(output, "{formatted}")?;
+            }
         }
+        Ok(())
     }
 
     /// Get the full path with formatted filename and extension.
@@ -376,43 +1668,69 @@ impl Dots {
         Ok(path.with_file_name(formatted_name))
     }
 
+    /// Get the formatting settings for a file, applying the matching extension rule if there is one.
+    /// Prefix and suffix from the command line take precedence over the extension rule.
+    fn file_name_rule(&self, path: &Path, extension: &str) -> NameRule<'_> {
+        let mut rule = self.config.name_rule();
+        if let Some(extension_rule) = self.config.extension_rules.get(extension) {
+            rule.extension_rule = Some(&extension_rule.name);
+            rule.convert_case |= extension_rule.case.unwrap_or_default();
+            rule.cleanup = extension_rule.cleanup.unwrap_or(rule.cleanup);
+            if rule.prefix.is_none() {
+                rule.prefix = extension_rule
+                    .prefix
+                    .as_deref()
+                    .map(|template| expand_template(template, path));
+            }
+            if rule.suffix.is_none() {
+                rule.suffix = extension_rule
+                    .suffix
+                    .as_deref()
+                    .map(|template| expand_template(template, path));
+            }
+            rule.replace = &extension_rule.replace;
+            rule.regex_replace = &extension_rule.regex_replace;
+        }
+        rule
+    }
+
     /// Format the file name stem without the file extension
     fn format_name(&self, file_name: &str) -> String {
+        self.format_name_with_rule(file_name, &self.config.name_rule())
+    }
+
+    /// Format the file name stem using the given formatting settings.
+    fn format_name_with_rule(&self, file_name: &str, rule: &NameRule) -> String {
+        // The replace helpers only allocate a new string when something matches
+        let mut new_name = file_name.to_string();
+
         // Apply static replacements
-        let mut new_name = REPLACE
-            .iter()
-            .fold(file_name.to_string(), |acc, &(pattern, replacement)| {
-                acc.replace(pattern, replacement)
-            });
+        for (pattern, replacement) in REPLACE {
+            replace_in_place(&mut new_name, pattern, replacement);
+        }
 
         // Apply extra replacements from args and user config
-        new_name = self
-            .config
-            .replace
-            .iter()
-            .fold(new_name, |acc, (pattern, replacement)| {
-                acc.replace(pattern, replacement)
-            });
+        for (pattern, replacement) in &self.config.replace {
+            replace_in_place(&mut new_name, pattern, replacement);
+        }
 
         // Apply regex replacements from args and user config
-        if !self.config.regex_replace.is_empty() {
-            for (regex, replacement) in &self.config.regex_replace {
-                new_name = regex.replace_all(&new_name, replacement).to_string();
-            }
+        for (regex, replacement) in &self.config.regex_replace {
+            regex_replace_in_place(&mut new_name, regex, replacement);
         }
 
-        new_name = RE_BRACKETS.replace_all(&new_name, ".").to_string();
-        new_name = RE_DOTCOM.replace_all(&new_name, ".").to_string();
-        new_name = RE_EXCLAMATION.replace_all(&new_name, ".").to_string();
-        new_name = RE_WHITESPACE.replace_all(&new_name, ".").to_string();
-        new_name = RE_DOTS.replace_all(&new_name, ".").to_string();
+        for regex in [&*RE_BRACKETS, &*RE_DOTCOM, &*RE_EXCLAMATION, &*RE_WHITESPACE, &*RE_DOTS] {
+            regex_replace_in_place(&mut new_name, regex, ".");
+        }
 
         Self::remove_special_characters(&mut new_name);
-        Self::remove_random_identifiers(&mut new_name);
+        if rule.cleanup {
+            Self::remove_random_identifiers(&mut new_name);
+        }
 
-        new_name = new_name.trim_start_matches('.').trim_end_matches('.').to_string();
+        trim_dots(&mut new_name);
 
-        if self.config.convert_case {
+        if rule.convert_case {
             new_name = new_name.to_lowercase();
         }
 
@@ -421,10 +1739,24 @@ impl Dots {
         new_name = titlecase::titlecase(&new_name);
         new_name = new_name.replace(' ', ".");
 
+        remove_duplicate_years(&mut new_name);
+        if let Some(order) = &self.config.token_order {
+            reorder_tokens(&mut new_name, order);
+        }
+
         // Fix encoding capitalization
-        new_name = new_name.replace("X265", "x265").replace("X264", "x264");
+        replace_in_place(&mut new_name, "X265", "x265");
+        replace_in_place(&mut new_name, "X264", "x264");
+
+        // Apply extension rule replacements
+        for (pattern, replacement) in rule.replace {
+            replace_in_place(&mut new_name, pattern, replacement);
+        }
+        for (regex, replacement) in rule.regex_replace {
+            regex_replace_in_place(&mut new_name, regex, replacement);
+        }
 
-        if let Some(ref prefix) = self.config.prefix {
+        if let Some(ref prefix) = rule.prefix {
             if new_name.contains(prefix) {
                 new_name = new_name.replace(prefix, "");
             }
@@ -436,7 +1768,7 @@ impl Dots {
                 new_name = format!("{prefix}.{new_name}");
             }
         }
-        if let Some(ref suffix) = self.config.suffix {
+        if let Some(ref suffix) = rule.suffix {
             if new_name.contains(suffix) {
                 new_name = new_name.replace(suffix, "");
             }
@@ -457,8 +1789,14 @@ impl Dots {
             self.move_to_end(&mut new_name);
         }
 
-        new_name = RE_DOTS.replace_all(&new_name, ".").to_string();
-        new_name = new_name.trim_start_matches('.').trim_end_matches('.').to_string();
+        regex_replace_in_place(&mut new_name, &RE_DOTS, ".");
+        trim_dots(&mut new_name);
+        if !self.config.no_final_cleanup {
+            final_cleanup(&mut new_name);
+        }
+        if self.config.year_brackets {
+            restore_year_brackets(&mut new_name, file_name);
+        }
         new_name
     }
 
@@ -480,12 +1818,14 @@ impl Dots {
 
     /// Only retain alphanumeric characters and a few common filename characters
     fn remove_special_characters(name: &mut String) {
+        let is_allowed = |c: char| c.is_alphanumeric() || c == '-' || c == '_' || c == '.' || c == '\'' || c == '&';
+        if name.chars().all(is_allowed) {
+            return;
+        }
         let cleaned: String = name
             // Split the string into graphemes (for handling emojis and complex characters)
             .graphemes(true)
-            .filter(|g| {
-                g.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == '.' || c == '\'' || c == '&')
-            })
+            .filter(|g| g.chars().all(is_allowed))
             .collect();
 
         *name = cleaned;
@@ -503,7 +1843,12 @@ impl Dots {
             }
         });
 
-        *name = result.trim().to_string();
+        if let Cow::Owned(result) = result {
+            *name = result;
+        }
+        if name.trim().len() != name.len() {
+            *name = name.trim().to_string();
+        }
     }
 
     fn has_at_least_six_digits(s: &str) -> bool {
@@ -511,10 +1856,10 @@ impl Dots {
     }
 
     /// Rename a file with an intermediate temp file to work around case-insensitive file systems.
-    fn rename_with_temp_file(path: &PathBuf, new_path: &PathBuf) -> std::io::Result<()> {
-        let temp_file = cli_tools::append_extension_to_path(new_path.clone(), ".tmp");
-        fs::rename(path, &temp_file)?;
-        fs::rename(&temp_file, new_path)
+    fn rename_with_temp_file(fs_ops: &impl FsOps, path: &Path, new_path: &Path) -> Result<()> {
+        let temp_file = cli_tools::append_extension_to_path(new_path.to_path_buf(), ".tmp");
+        fs_ops.rename(path, &temp_file)?;
+        fs_ops.rename(&temp_file, new_path)
     }
 }
 
@@ -561,30 +1906,193 @@ impl Args {
 impl Config {
     /// Create config from given command line args and user config file.
     pub fn from_args(args: Args) -> Result<Self> {
-        let user_config = DotsConfig::get_user_config();
+        let user_config = DotsConfig::get_user_config()?;
         let mut replace = args.parse_substitutes();
         replace.extend(user_config.replace);
         let mut regex_replace = args.parse_regex_substitutes()?;
         let config_regex = Self::compile_regex_patterns(&user_config.regex_replace)?;
         regex_replace.extend(config_regex);
+        let extension_rules = Self::compile_extension_rules(user_config.extensions)?;
+        // Validate the token order even when reordering is off so config errors show up right away
+        let token_order = TokenClass::parse_order(user_config.token_order.as_deref())?;
+        if let (Some(min_size), Some(max_size)) = (args.min_size, args.max_size) {
+            if min_size > max_size {
+                anyhow::bail!("Minimum size {min_size} is larger than maximum size {max_size}");
+            }
+        }
         Ok(Self {
             replace,
             regex_replace,
             move_to_start: user_config.move_to_start,
             move_to_end: user_config.move_to_end,
+            skip_rules: cli_tools::SkipRules {
+                skip_names: user_config.skip_names,
+                skip_extensions: user_config.skip_extensions,
+                exclude: args.exclude,
+                exclude_dirs: args.exclude_dir,
+                ..cli_tools::SkipRules::default()
+            },
+            extension_rules,
             prefix: args.prefix,
             suffix: args.suffix,
+            files_from: args.files_from,
+            date_filter: if args.with_dates {
+                Some(DateFilter::WithDates)
+            } else if args.without_dates {
+                Some(DateFilter::WithoutDates)
+            } else {
+                None
+            },
+            preserve_original: args.preserve_original.or(user_config.preserve_original),
+            ignore_missing: args.ignore_missing,
+            check_order: args.check_order || user_config.check_order,
             convert_case: args.case,
             prefix_dir: args.prefix_dir || user_config.prefix_dir,
             debug: args.debug || user_config.debug,
-            directory: args.directory || user_config.directory,
+            dedupe: args.dedupe,
+            dedupe_keep: args.dedupe_keep,
+            dedupe_normalization: args.dedupe_normalization || user_config.dedupe_normalization,
+            dedupe_verify: args.dedupe_verify,
+            target: args
+                .only
+                .or(user_config.only)
+                .unwrap_or(if args.directory || user_config.directory {
+                    RenameTarget::Both
+                } else {
+                    RenameTarget::Files
+                }),
             dryrun: args.print || user_config.dryrun,
+            gentle: args.gentle || user_config.gentle,
+            interactive: args.interactive,
+            no_final_cleanup: args.no_final_cleanup,
+            no_local_rules: args.no_local_config,
+            limit: args.limit,
+            sample: args.sample,
+            seed: args.seed,
+            min_size: args.min_size,
+            max_size: args.max_size,
+            lock_wait: args.lock_wait.unwrap_or_default(),
+            gentle_delay: Duration::from_millis(user_config.gentle_delay_ms.unwrap_or(DEFAULT_GENTLE_DELAY_MS)),
+            retries: user_config.retries.unwrap_or(DEFAULT_RETRIES).min(MAX_RETRIES),
             overwrite: args.force || user_config.overwrite,
             recursive: args.recursive || user_config.recursive,
+            sync: args.sync,
             verbose: args.verbose || user_config.verbose,
+            verify: args.verify,
+            year_brackets: args.year_brackets || user_config.year_brackets,
+            token_order: (args.reorder || user_config.reorder).then_some(token_order),
         })
     }
 
+    /// Add the local config rules after the current ones.
+    /// Patterns already given on the command line and an existing prefix or suffix take precedence.
+    /// Returns the current rules for restoring them later.
+    fn merge_local(&mut self, local: LocalConfig) -> GlobalRules {
+        let global = GlobalRules {
+            replace: self.replace.clone(),
+            regex_replace: self.regex_replace.clone(),
+            prefix: self.prefix.clone(),
+            suffix: self.suffix.clone(),
+        };
+        let substitutes = local
+            .substitute
+            .into_iter()
+            .chain(local.remove.into_iter().map(|pattern| (pattern, String::new())));
+        for (pattern, replacement) in substitutes {
+            if !self.replace.iter().any(|(existing, _)| *existing == pattern) {
+                self.replace.push((pattern, replacement));
+            }
+        }
+        for (pattern, replacement) in local.regex {
+            if self
+                .regex_replace
+                .iter()
+                .any(|(existing, _)| existing.as_str() == pattern)
+            {
+                continue;
+            }
+            // Validated when the config was loaded
+            if let Ok(regex) = Regex::new(&pattern) {
+                self.regex_replace.push((regex, replacement));
+            }
+        }
+        if self.prefix.is_none() {
+            self.prefix = local.prefix;
+        }
+        if self.suffix.is_none() {
+            self.suffix = local.suffix;
+        }
+        global
+    }
+
+    /// Restore the rules saved before merging a local config.
+    fn restore_rules(&mut self, rules: GlobalRules) {
+        self.replace = rules.replace;
+        self.regex_replace = rules.regex_replace;
+        self.prefix = rules.prefix;
+        self.suffix = rules.suffix;
+    }
+
+    /// Check if a minimum or maximum file size is set.
+    const fn has_size_filter(&self) -> bool {
+        self.min_size.is_some() || self.max_size.is_some()
+    }
+
+    /// Global formatting settings without extension rules.
+    fn name_rule(&self) -> NameRule<'_> {
+        NameRule {
+            extension_rule: None,
+            convert_case: self.convert_case,
+            cleanup: true,
+            prefix: self.prefix.clone(),
+            suffix: self.suffix.clone(),
+            replace: &[],
+            regex_replace: &[],
+        }
+    }
+
+    /// Compile extension rules from config and map them by lowercase extension.
+    /// A rule key can be a single extension or a comma-separated group of extensions.
+    fn compile_extension_rules(rules: BTreeMap<String, ExtensionRuleConfig>) -> Result<HashMap<String, ExtensionRule>> {
+        let mut extension_rules: HashMap<String, ExtensionRule> = HashMap::new();
+        for (name, rule) in rules {
+            let extensions: Vec<String> = name
+                .split(',')
+                .map(|extension| extension.trim().trim_start_matches('.').to_lowercase())
+                .filter(|extension| !extension.is_empty())
+                .collect();
+            if extensions.is_empty() {
+                anyhow::bail!("Extension rule without extensions: [{name}]");
+            }
+            let regex_replace = Self::compile_regex_patterns(&rule.regex_replace)
+                .with_context(|| format!("Extension rule [{name}]"))?;
+            for extension in extensions {
+                if let Some(existing) = extension_rules.get(&extension) {
+                    anyhow::bail!(
+                        "Extension '{extension}' is in multiple rules: [{}] and [{name}]",
+                        existing.name
+                    );
+                }
+                extension_rules.insert(
+                    extension,
+                    ExtensionRule {
+                        name: name.clone(),
+                        case: rule.case,
+                        cleanup: rule.cleanup,
+                        prefix: rule.prefix.clone(),
+                        suffix: rule.suffix.clone(),
+                        replace: rule.replace.clone(),
+                        regex_replace: regex_replace
+                            .iter()
+                            .map(|(regex, replacement)| (regex.clone(), replacement.clone()))
+                            .collect(),
+                    },
+                );
+            }
+        }
+        Ok(extension_rules)
+    }
+
     fn compile_regex_patterns(regex_pairs: &[(String, String)]) -> Result<Vec<(Regex, String)>> {
         let mut compiled_pairs = Vec::new();
 
@@ -597,16 +2105,53 @@ impl Config {
     }
 }
 
+impl LocalConfig {
+    /// Read and validate a local config file.
+    fn load(path: &Path) -> Result<Self> {
+        let content =
+            fs::read_to_string(path).with_context(|| format!("Failed to read local config: {}", path.display()))?;
+        let config: Self =
+            toml::from_str(&content).with_context(|| format!("Invalid local config: {}", path.display()))?;
+        for (index, (pattern, _)) in config.substitute.iter().enumerate() {
+            if pattern.trim().is_empty() {
+                anyhow::bail!(
+                    "Empty pattern in {} key 'substitute' entry {}",
+                    path.display(),
+                    index + 1
+                );
+            }
+        }
+        for (index, pattern) in config.remove.iter().enumerate() {
+            if pattern.trim().is_empty() {
+                anyhow::bail!("Empty pattern in {} key 'remove' entry {}", path.display(), index + 1);
+            }
+        }
+        for (index, (pattern, _)) in config.regex.iter().enumerate() {
+            Regex::new(pattern).with_context(|| {
+                format!(
+                    "Invalid regex '{pattern}' in {} key 'regex' entry {}",
+                    path.display(),
+                    index + 1
+                )
+            })?;
+        }
+        Ok(config)
+    }
+}
+
+/// Find the nearest local config file in the directory or its parents.
+fn find_local_config(dir: &Path) -> Option<PathBuf> {
+    let dir = std::path::absolute(dir).ok()?;
+    dir.ancestors()
+        .map(|ancestor| ancestor.join(LOCAL_CONFIG))
+        .find(|path| path.is_file())
+}
+
 impl DotsConfig {
-    /// Try to read user config from the file if it exists.
+    /// Read user config from the file if it exists.
     /// Otherwise, fall back to default config.
-    fn get_user_config() -> Self {
-        cli_tools::config::CONFIG_PATH
-            .as_deref()
-            .and_then(|path| fs::read_to_string(path).ok())
-            .and_then(|config_string| toml::from_str::<UserConfig>(&config_string).ok())
-            .map(|config| config.dots)
-            .unwrap_or_default()
+    fn get_user_config() -> Result<Self> {
+        Ok(cli_tools::config::load_tool_config::<Self>("dots")?.unwrap_or_default())
     }
 }
 
@@ -624,11 +2169,24 @@ impl fmt::Display for Config {
         };
         writeln!(f, "Config:")?;
         writeln!(f, "  debug:      {}", cli_tools::colorize_bool(self.debug))?;
+        writeln!(f, "  check order: {}", cli_tools::colorize_bool(self.check_order))?;
         writeln!(f, "  dryrun:     {}", cli_tools::colorize_bool(self.dryrun))?;
+        writeln!(f, "  gentle:     {}", cli_tools::colorize_bool(self.gentle))?;
+        writeln!(
+            f,
+            "  dedupe nfd: {}",
+            cli_tools::colorize_bool(self.dedupe_normalization)
+        )?;
         writeln!(f, "  prefix dir: {}", cli_tools::colorize_bool(self.prefix_dir))?;
         writeln!(f, "  overwrite:  {}", cli_tools::colorize_bool(self.overwrite))?;
         writeln!(f, "  recursive:  {}", cli_tools::colorize_bool(self.recursive))?;
+        writeln!(f, "  only:       {}", self.target.description())?;
+        writeln!(f, "  min size:   {:?}", self.min_size)?;
+        writeln!(f, "  max size:   {:?}", self.max_size)?;
         writeln!(f, "  verbose:    {}", cli_tools::colorize_bool(self.verbose))?;
+        if let Some(order) = &self.token_order {
+            writeln!(f, "  reorder:    {}", order.iter().map(|class| class.key()).join(", "))?;
+        }
         writeln!(
             f,
             "  prefix:     \"{}\"",
@@ -640,158 +2198,2420 @@ impl fmt::Display for Config {
             self.suffix.as_ref().unwrap_or(&String::new())
         )?;
         writeln!(f, "  {replace}")?;
-        writeln!(f, "  {regex_replace}")
+        writeln!(f, "  {regex_replace}")?;
+        let extensions = self.extension_rules.keys().sorted().join(", ");
+        writeln!(f, "  extension rules: [{extensions}]")
+    }
+}
+
+impl RenameTarget {
+    const fn includes_files(self) -> bool {
+        matches!(self, Self::Files | Self::Both)
+    }
+
+    const fn includes_directories(self) -> bool {
+        matches!(self, Self::Dirs | Self::Both)
+    }
+
+    const fn description(self) -> &'static str {
+        match self {
+            Self::Files => "files",
+            Self::Dirs => "directories",
+            Self::Both => "files or directories",
+        }
     }
 }
 
 impl fmt::Display for Dots {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "Root: {}", self.root.display())?;
+        writeln!(f, "Roots: {}", self.roots.iter().map(|root| root.display()).join(", "))?;
         write!(f, "{}", self.config)
     }
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
-    Dots::run_with_args(args)
+impl PolicyRule {
+    const fn description(self) -> &'static str {
+        match self {
+            Self::Characters => "Invalid characters",
+            Self::Length => "Too long",
+            Self::Spaces => "Contains spaces",
+            Self::Structure => "Not dot-separated",
+            Self::Extension => "Extension not allowed",
+        }
+    }
 }
 
-#[cfg(test)]
-mod dots_tests {
-    use super::*;
-
-    static DOTS: LazyLock<Dots> = LazyLock::new(Dots::default);
-
-    #[test]
-    fn test_format_basic() {
-        assert_eq!(DOTS.format_name("Some file"), "Some.File");
-        assert_eq!(DOTS.format_name("some file"), "Some.File");
-        assert_eq!(DOTS.format_name("word"), "Word");
-        assert_eq!(DOTS.format_name("__word__"), "Word");
+impl NamingPolicy {
+    fn from_config(config: &NamingPolicyConfig) -> Result<Self> {
+        let allowed_characters = config
+            .allowed_characters
+            .as_ref()
+            .map(|pattern| {
+                Regex::new(&format!("^(?:{pattern})+$")).with_context(|| format!("Invalid regex: '{pattern}'"))
+            })
+            .transpose()?;
+        let extensions = config
+            .extensions
+            .iter()
+            .map(|policy| {
+                let directory =
+                    Regex::new(&policy.directory).with_context(|| format!("Invalid regex: '{}'", policy.directory))?;
+                let allowed = policy
+                    .allowed
+                    .iter()
+                    .map(|extension| extension.trim_start_matches('.').to_lowercase())
+                    .collect();
+                Ok((directory, allowed))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            allowed_characters,
+            max_length: config.max_length,
+            dot_separated: config.dot_separated,
+            no_spaces: config.no_spaces,
+            extensions,
+        })
     }
 
-    #[test]
-    fn test_format_name_with_newlines() {
-        assert_eq!(
-            DOTS.format_name("Meeting \tNotes \n(2023) - Draft\r\n"),
-            "Meeting.Notes.2023.Draft"
-        );
-    }
+    /// Check the file name against the policy.
+    /// The path should be relative to the checked root directory.
+    fn check(&self, relative_path: &Path) -> Vec<PolicyRule> {
+        let mut violations = Vec::new();
+        let name = relative_path
+            .file_name()
+            .map(cli_tools::os_str_to_string)
+            .unwrap_or_default();
+        let stem = relative_path
+            .file_stem()
+            .map(cli_tools::os_str_to_string)
+            .unwrap_or_default();
 
-    #[test]
-    fn test_format_name_no_brackets() {
-        assert_eq!(DOTS.format_name("John Doe - Document"), "John.Doe.Document");
+        if self
+            .allowed_characters
+            .as_ref()
+            .is_some_and(|regex| !regex.is_match(&name))
+        {
+            violations.push(PolicyRule::Characters);
+        }
+        if self.max_length.is_some_and(|max| name.chars().count() > max) {
+            violations.push(PolicyRule::Length);
+        }
+        if self.no_spaces && name.contains(char::is_whitespace) {
+            violations.push(PolicyRule::Spaces);
+        }
+        if self.dot_separated && stem.split('.').any(str::is_empty) {
+            violations.push(PolicyRule::Structure);
+        }
+        let directory = relative_path
+            .parent()
+            .map(|parent| parent.to_string_lossy().replace('\\', "/"))
+            .unwrap_or_default();
+        if let Some((_, allowed)) = self.extensions.iter().find(|(regex, _)| regex.is_match(&directory)) {
+            let extension = relative_path
+                .extension()
+                .map(|extension| cli_tools::os_str_to_string(extension).to_lowercase())
+                .unwrap_or_default();
+            if !allowed.contains(&extension) {
+                violations.push(PolicyRule::Extension);
+            }
+        }
+        violations
     }
+}
 
-    #[test]
-    fn test_format_name_with_brackets() {
-        assert_eq!(
-            DOTS.format_name("Project Report - [Final Version]"),
-            "Project.Report.Final.Version"
-        );
-        assert_eq!(DOTS.format_name("Code {Snippet} (example)"), "Code.Snippet.Example");
+/// Check all file names under the root directory against the naming policy.
+/// Returns the relative paths of violating files grouped by rule.
+fn find_policy_violations(
+    root: &Path,
+    policy: &NamingPolicy,
+    skip_rules: &cli_tools::SkipRules,
+) -> BTreeMap<PolicyRule, Vec<PathBuf>> {
+    let mut violations: BTreeMap<PolicyRule, Vec<PathBuf>> = BTreeMap::new();
+    // A single file is checked by its file name
+    let base = if root.is_file() {
+        root.parent().unwrap_or(root)
+    } else {
+        root
+    };
+    for entry in WalkDir::new(root)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || !skip_rules.should_skip(e))
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+    {
+        let relative_path = entry.path().strip_prefix(base).unwrap_or_else(|_| entry.path());
+        for rule in policy.check(relative_path) {
+            violations.entry(rule).or_default().push(relative_path.to_path_buf());
+        }
     }
+    violations
+}
 
-    #[test]
+/// Get the walk entry for a single path.
+fn path_entry(path: &Path) -> Option<walkdir::DirEntry> {
+    WalkDir::new(path).max_depth(0).into_iter().next()?.ok()
+}
+
+/// Remove roots that are already covered by another root.
+///
+/// A nested root is covered by its parent root in recursive mode,
+/// and a file is covered by the directory it is directly in.
+fn dedupe_roots(roots: &[PathBuf], recursive: bool) -> Vec<PathBuf> {
+    roots
+        .iter()
+        .filter(|root| {
+            !roots.iter().any(|other| {
+                other != *root
+                    && other.is_dir()
+                    && root.starts_with(other)
+                    && (recursive || (root.is_file() && root.parent() == Some(other.as_path())))
+            })
+        })
+        .cloned()
+        .collect()
+}
+
+/// Check file names against the naming policy from the user config and print the violations by rule.
+/// Fails if any violations are found.
+fn check_naming_policy(paths: &[String]) -> Result<()> {
+    let roots = dedupe_roots(&cli_tools::resolve_input_paths(paths)?, true);
+    let user_config = DotsConfig::get_user_config()?;
+    let policy = NamingPolicy::from_config(
+        user_config
+            .policy
+            .as_ref()
+            .context("No naming policy in the config file, add a [dots.policy] section")?,
+    )?;
+    let skip_rules = cli_tools::SkipRules {
+        skip_names: user_config.skip_names,
+        skip_extensions: user_config.skip_extensions,
+        ..cli_tools::SkipRules::default()
+    };
+
+    let mut violations: BTreeMap<PolicyRule, Vec<PathBuf>> = BTreeMap::new();
+    for root in &roots {
+        for (rule, paths) in find_policy_violations(root, &policy, &skip_rules) {
+            // Show which root the path is from when checking multiple roots
+            let paths = paths.into_iter().map(|path| match root.file_name() {
+                Some(name) if roots.len() > 1 => Path::new(name).join(path),
+                _ => path,
+            });
+            violations.entry(rule).or_default().extend(paths);
+        }
+    }
+    if violations.is_empty() {
+        println!("{}", "All file names follow the naming policy".green());
+        return Ok(());
+    }
+    for (rule, paths) in &violations {
+        println!("{}", format!("{} ({}):", rule.description(), paths.len()).bold());
+        for path in paths {
+            println!("  {}", path.display());
+        }
+    }
+    let total: usize = violations.values().map(Vec::len).sum();
+    Err(anyhow!(
+        "Found {} of the naming policy",
+        format_count(total, "violation", "violations")
+    ))
+}
+
+/// Ask the user a yes or no question. Anything other than yes counts as no.
+fn confirm(question: &str) -> bool {
+    read_answer(&format!("{question} [y/N]"), &mut io::stdin().lock())
+        .is_some_and(|answer| matches!(answer.to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Print the question and read the trimmed answer from the input.
+/// Returns `None` if the input has ended or can not be read.
+fn read_answer(question: &str, input: &mut impl BufRead) -> Option<String> {
+    print!("{question} ");
+    io::stdout().flush().ok()?;
+    let mut answer = String::new();
+    match input.read_line(&mut answer) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(answer.trim().to_string()),
+    }
+}
+
+/// Ask what to do with a proposed rename until a valid answer is given.
+/// An empty answer skips the rename, and the end of input quits.
+fn ask_rename_decision(input: &mut impl BufRead) -> RenameDecision {
+    loop {
+        let Some(answer) = read_answer("Apply rename? [y]es / [N]o / [e]dit / [a]ll / [q]uit:", input) else {
+            return RenameDecision::Quit;
+        };
+        match answer.to_lowercase().as_str() {
+            "y" | "yes" => return RenameDecision::Apply,
+            "n" | "no" | "" => return RenameDecision::Skip,
+            "a" | "all" => return RenameDecision::ApplyAll,
+            "q" | "quit" => return RenameDecision::Quit,
+            "e" | "edit" => {
+                let Some(name) = read_answer("New name:", input) else {
+                    return RenameDecision::Quit;
+                };
+                if name.is_empty() {
+                    continue;
+                }
+                if name.contains(['/', '\\']) {
+                    println!("{}", "Name can not contain path separators".yellow());
+                    continue;
+                }
+                return RenameDecision::Edit(name);
+            }
+            _ => println!("{}", format!("Unknown answer: {answer}").yellow()),
+        }
+    }
+}
+
+/// Find renamed files that would change position in the natural sort order of their directory.
+fn find_sort_order_changes(renames: &[(PathBuf, PathBuf)]) -> Vec<PathBuf> {
+    let mut directories: BTreeMap<PathBuf, HashMap<String, String>> = BTreeMap::new();
+    for (path, new_path) in renames {
+        if let (Some(parent), Some(name), Some(new_name)) = (path.parent(), path.file_name(), new_path.file_name()) {
+            directories
+                .entry(parent.to_path_buf())
+                .or_default()
+                .insert(cli_tools::os_str_to_string(name), cli_tools::os_str_to_string(new_name));
+        }
+    }
+
+    let mut changes = Vec::new();
+    for (directory, directory_renames) in directories {
+        let mut siblings: Vec<String> = fs::read_dir(&directory)
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .filter(|entry| entry.path().is_file())
+                    .map(|entry| cli_tools::os_str_to_string(&entry.file_name()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        for name in directory_renames.keys() {
+            if !siblings.contains(name) {
+                siblings.push(name.clone());
+            }
+        }
+        changes.extend(
+            sort_order_changes(&siblings, &directory_renames)
+                .into_iter()
+                .map(|name| directory.join(name)),
+        );
+    }
+    changes
+}
+
+/// Return the renamed names whose position in the natural sort order changes after renaming.
+fn sort_order_changes(names: &[String], renames: &HashMap<String, String>) -> Vec<String> {
+    let new_name = |name: &String| renames.get(name).unwrap_or(name).clone();
+    let mut before: Vec<&String> = names.iter().collect();
+    before.sort_by(|a, b| cli_tools::natural_sort_cmp(a, b));
+    let mut after = before.clone();
+    after.sort_by(|a, b| cli_tools::natural_sort_cmp(&new_name(a), &new_name(b)));
+
+    before
+        .iter()
+        .zip(&after)
+        .filter(|(old, new)| old != new)
+        .map(|(old, _)| *old)
+        .filter(|name| renames.contains_key(*name))
+        .cloned()
+        .collect()
+}
+
+/// Parse newline-separated paths relative to the root directory, or absolute paths.
+/// Returns the existing files and the missing ones with their line numbers.
+fn parse_file_list(content: &str, root: &Path) -> (Vec<PathBuf>, Vec<(usize, String)>) {
+    let mut files = Vec::new();
+    let mut missing = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            continue;
+        }
+        let path = root.join(line);
+        if path.is_file() {
+            files.push(path);
+        } else {
+            missing.push((index + 1, line.to_string()));
+        }
+    }
+    (files, missing)
+}
+
+/// Replace all occurrences of the pattern, only allocating a new string if the pattern is found.
+fn replace_in_place(name: &mut String, pattern: &str, replacement: &str) {
+    if name.contains(pattern) {
+        *name = name.replace(pattern, replacement);
+    }
+}
+
+/// Replace all regex matches, only allocating a new string if the regex matches.
+fn regex_replace_in_place(name: &mut String, regex: &Regex, replacement: &str) {
+    if let Cow::Owned(replaced) = regex.replace_all(name, replacement) {
+        *name = replaced;
+    }
+}
+
+/// Remove leading and trailing dots.
+fn trim_dots(name: &mut String) {
+    let trimmed = name.trim_matches('.');
+    if trimmed.len() != name.len() {
+        *name = trimmed.to_string();
+    }
+}
+
+/// Check that a rename is visible on disk: the new path exists and the old path is gone.
+/// Checks again after a short delay before giving up.
+fn verify_rename(path: &Path, new_path: &Path, capitalization_change_only: bool) -> bool {
+    let check = || {
+        if capitalization_change_only {
+            // Both names point to the same file on case-insensitive file systems,
+            // so check the exact names in the directory listing instead
+            is_listed_in_parent(new_path) && !is_listed_in_parent(path)
+        } else {
+            new_path.exists() && !path.exists()
+        }
+    };
+    if check() {
+        return true;
+    }
+    thread::sleep(VERIFY_RETRY_DELAY);
+    check()
+}
+
+/// Check if a path has left its old location after an unverified rename,
+/// so paths under it should use the new location.
+fn is_moved_from(path: &Path, new_path: &Path, capitalization_change_only: bool) -> bool {
+    if capitalization_change_only {
+        !is_listed_in_parent(path)
+    } else {
+        !path.exists() && new_path.exists()
+    }
+}
+
+/// Check if the parent directory contains an entry with exactly this name.
+fn is_listed_in_parent(path: &Path) -> bool {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return false;
+    };
+    fs::read_dir(dir).is_ok_and(|entries| entries.filter_map(Result::ok).any(|entry| entry.file_name() == name))
+}
+
+/// Check if two paths are duplicate files and select which one to remove.
+/// Files are duplicates if they have the same size, and also the same contents when `compare_contents` is set.
+fn select_duplicate_to_trash(
+    path: &Path,
+    existing: &Path,
+    keep: DedupeKeep,
+    compare_contents: bool,
+) -> Result<Option<PathBuf>> {
+    if !path.is_file() || !existing.is_file() || !files_are_identical(path, existing, compare_contents)? {
+        return Ok(None);
+    }
+    let modified = |file: &Path| {
+        fs::metadata(file)
+            .and_then(|metadata| metadata.modified())
+            .with_context(|| format!("Failed to read modification time: {}", file.display()))
+    };
+    let path_is_newer = modified(path)? > modified(existing)?;
+    let keep_path = match keep {
+        DedupeKeep::Newer => path_is_newer,
+        DedupeKeep::Older => !path_is_newer,
+    };
+    Ok(Some(if keep_path { existing } else { path }.to_path_buf()))
+}
+
+/// Compare file sizes, and optionally the contents.
+fn files_are_identical(first: &Path, second: &Path, compare_contents: bool) -> Result<bool> {
+    const CHUNK_SIZE: usize = 64 * 1024;
+    if fs::metadata(first)?.len() != fs::metadata(second)?.len() {
+        return Ok(false);
+    }
+    if !compare_contents {
+        return Ok(true);
+    }
+    let open = |file: &Path| fs::File::open(file).with_context(|| format!("Failed to open file: {}", file.display()));
+    let mut first_file = io::BufReader::new(open(first)?);
+    let mut second_file = io::BufReader::new(open(second)?);
+    let mut first_buffer = vec![0; CHUNK_SIZE];
+    let mut second_buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let read = io::Read::read(&mut first_file, &mut first_buffer)?;
+        if read == 0 {
+            return Ok(true);
+        }
+        // The sizes match, so the second file has at least as many bytes left
+        io::Read::read_exact(&mut second_file, &mut second_buffer[..read])?;
+        if first_buffer[..read] != second_buffer[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+/// Flush directory metadata to disk so renames survive removing the drive.
+#[cfg(unix)]
+fn sync_directory(dir: &Path) -> std::io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+/// Directories can not be opened for syncing on this platform.
+#[cfg(not(unix))]
+fn sync_directory(_dir: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Remove artifacts left behind by the earlier replacements:
+/// empty brackets, runs of separators around dots, and separators at the start and end.
+/// Running this again on the result does not change it.
+fn final_cleanup(name: &mut String) {
+    loop {
+        let length = name.len();
+        regex_replace_in_place(name, &RE_EMPTY_BRACKETS, "");
+        regex_replace_in_place(name, &RE_SEPARATOR_RUN, ".");
+        let trimmed = name.trim_matches(['.', '-', '_']);
+        if trimmed.len() != name.len() {
+            *name = trimmed.to_string();
+        }
+        if name.len() == length {
+            break;
+        }
+    }
+}
+
+/// Check if a name part is a year like "2008".
+fn is_year(part: &str) -> bool {
+    part.len() == 4 && (part.starts_with("19") || part.starts_with("20")) && part.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Remove a year that repeats an identical year a few name parts earlier,
+/// for example "Movie.2008.2008.Remaster" left behind after removing brackets.
+/// Different years are kept since titles can legitimately contain a year.
+fn remove_duplicate_years(name: &mut String) {
+    let parts: Vec<&str> = name.split('.').collect();
+    let mut kept: Vec<&str> = Vec::with_capacity(parts.len());
+    let mut removed = false;
+    for part in parts {
+        let is_duplicate = is_year(part)
+            && kept
+                .iter()
+                .rev()
+                .take(YEAR_DEDUPE_DISTANCE + 1)
+                .any(|previous| *previous == part);
+        if is_duplicate {
+            removed = true;
+        } else {
+            kept.push(part);
+        }
+    }
+    if removed {
+        *name = kept.join(".");
+    }
+}
+
+impl TokenClass {
+    /// Config file key for the token class.
+    const fn key(self) -> &'static str {
+        match self {
+            Self::Title => "title",
+            Self::Year => "year",
+            Self::Episode => "episode",
+            Self::Resolution => "resolution",
+            Self::Source => "source",
+            Self::Codec => "codec",
+        }
+    }
+
+    /// Parse the token order from config file keys, or use the default order.
+    /// Classes missing from the config keep their default relative order after the listed ones.
+    fn parse_order(keys: Option<&[String]>) -> Result<Vec<Self>> {
+        let Some(keys) = keys else {
+            return Ok(DEFAULT_TOKEN_ORDER.to_vec());
+        };
+        let mut order: Vec<Self> = Vec::with_capacity(DEFAULT_TOKEN_ORDER.len());
+        for key in keys {
+            let class = DEFAULT_TOKEN_ORDER
+                .into_iter()
+                .find(|class| class.key() == key.trim().to_lowercase())
+                .with_context(|| {
+                    format!(
+                        "Unknown token order key '{key}', expected one of: {}",
+                        DEFAULT_TOKEN_ORDER.iter().map(|class| class.key()).join(", ")
+                    )
+                })?;
+            if order.contains(&class) {
+                anyhow::bail!("Duplicate token order key '{key}'");
+            }
+            order.push(class);
+        }
+        for class in DEFAULT_TOKEN_ORDER {
+            if !order.contains(&class) {
+                order.push(class);
+            }
+        }
+        Ok(order)
+    }
+}
+
+/// Classify each dot-separated name part.
+/// Only the last year is classified as the release year,
+/// so years that are part of the title stay in place.
+fn classify_tokens<'a>(parts: &[&'a str]) -> Vec<(TokenClass, &'a str)> {
+    let last_year = parts.iter().rposition(|part| is_year(part));
+    let mut tokens: Vec<(TokenClass, &str)> = Vec::with_capacity(parts.len());
+    for (index, part) in parts.iter().enumerate() {
+        let upper = part.to_uppercase();
+        let follows_source = tokens.last().is_some_and(|(class, _)| *class == TokenClass::Source);
+        let class = if Some(index) == last_year {
+            TokenClass::Year
+        } else if RE_EPISODE.is_match(part) {
+            TokenClass::Episode
+        } else if cli_tools::tokens::is_resolution_token(part) {
+            TokenClass::Resolution
+        } else if SOURCE_TAGS.contains(&upper.as_str())
+            || (follows_source && SOURCE_SUFFIX_TAGS.contains(&upper.as_str()))
+        {
+            TokenClass::Source
+        } else if CODEC_TAGS.contains(&upper.as_str()) {
+            TokenClass::Codec
+        } else {
+            TokenClass::Title
+        };
+        tokens.push((class, part));
+    }
+    tokens
+}
+
+/// Reorder the name parts by token class.
+/// Unrecognized parts keep their relative order at the title position.
+fn reorder_tokens(name: &mut String, order: &[TokenClass]) {
+    let parts = cli_tools::tokens::split_dot_tokens(name);
+    let tokens = classify_tokens(&parts);
+    let reordered = order
+        .iter()
+        .flat_map(|class| {
+            tokens
+                .iter()
+                .filter(move |(token_class, _)| token_class == class)
+                .map(|(_, part)| *part)
+        })
+        .join(".");
+    if reordered != *name {
+        *name = reordered;
+    }
+}
+
+/// Put years that were in brackets or parentheses in the original name back in parentheses.
+fn restore_year_brackets(name: &mut String, original: &str) {
+    for captures in RE_BRACKETED_YEAR.captures_iter(original) {
+        let year = &captures[1];
+        let wrapped = format!("({year})");
+        if name.split('.').any(|part| part == wrapped) {
+            continue;
+        }
+        let mut parts: Vec<&str> = name.split('.').collect();
+        if let Some(part) = parts.iter_mut().find(|part| **part == year) {
+            *part = &wrapped;
+            *name = parts.join(".");
+        }
+    }
+}
+
+/// Replace `{date}` in the template with the modification date of the file.
+fn expand_template(template: &str, path: &Path) -> String {
+    if !template.contains(DATE_PLACEHOLDER) {
+        return template.to_string();
+    }
+    let date = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map(|modified| {
+            chrono::DateTime::<chrono::Local>::from(modified)
+                .format("%Y.%m.%d")
+                .to_string()
+        })
+        .unwrap_or_default();
+    template.replace(DATE_PLACEHOLDER, &date)
+}
+
+/// Format count with the singular or plural name.
+fn format_count(count: usize, singular: &str, plural: &str) -> String {
+    if count == 1 {
+        format!("{count} {singular}")
+    } else {
+        format!("{count} {plural}")
+    }
+}
+
+/// Find files in the same directory whose names are identical after NFC normalization.
+///
+/// Returns the conflict groups as the file to keep, preferring the NFC-named file,
+/// and the remaining files with a different byte representation of the same name.
+fn find_normalization_conflicts(paths: &[PathBuf]) -> Vec<(PathBuf, Vec<PathBuf>)> {
+    let mut groups: BTreeMap<(PathBuf, String), Vec<PathBuf>> = BTreeMap::new();
+    for path in paths {
+        let Some(name) = path.file_name() else {
+            continue;
+        };
+        let parent = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        let normalized = cli_tools::os_str_to_string(name).nfc().collect::<String>();
+        groups.entry((parent, normalized)).or_default().push(path.clone());
+    }
+
+    groups
+        .into_iter()
+        .filter(|(_, group)| group.len() > 1)
+        .map(|((_, normalized), mut group)| {
+            group.sort();
+            let keep_index = group
+                .iter()
+                .position(|path| {
+                    path.file_name()
+                        .is_some_and(|name| name.to_str() == Some(normalized.as_str()))
+                })
+                .unwrap_or_default();
+            let keep = group.remove(keep_index);
+            (keep, group)
+        })
+        .collect()
+}
+
+/// Store the original name of a renamed path.
+///
+/// Falls back to the manifest file if extended attributes are not supported.
+/// If the path already has a stored original name, it is kept so the first name is preserved over multiple renames.
+fn store_original_name(mode: PreserveMode, path: &Path, new_path: &Path) -> Result<()> {
+    let original_name = cli_tools::os_str_to_string(path.file_name().context("Failed to get file name")?);
+    if mode == PreserveMode::Xattr {
+        if read_xattr_original(new_path).is_some() {
+            return Ok(());
+        }
+        match write_xattr_original(new_path, &original_name) {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                eprintln!(
+                    "{}",
+                    format!("Extended attributes not supported, using manifest instead: {e}").yellow()
+                );
+            }
+        }
+    }
+    let original_name = read_manifest_original(path)?.unwrap_or(original_name);
+    append_manifest_original(new_path, &original_name)
+}
+
+/// Get the stored original name for a path, checking the extended attribute first and then the manifest.
+fn read_original_name(path: &Path) -> Result<Option<String>> {
+    if let Some(name) = read_xattr_original(path) {
+        return Ok(Some(name));
+    }
+    read_manifest_original(path)
+}
+
+/// Add the renames of a run to the rename log in the directory.
+/// Only the most recent runs are kept.
+fn append_rename_log(dir: &Path, entries: Vec<RenameLogEntry>) -> Result<()> {
+    let mut log = read_rename_log(dir)?;
+    log.runs.push(entries);
+    let excess = log.runs.len().saturating_sub(MAX_LOGGED_RUNS);
+    log.runs.drain(..excess);
+    write_rename_log_file(dir, &log)
+}
+
+/// Read the rename log from the directory, or an empty log if there is none.
+fn read_rename_log(dir: &Path) -> Result<RenameLog> {
+    let path = dir.join(RENAME_LOG);
+    if !path.is_file() {
+        return Ok(RenameLog::default());
+    }
+    let content =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read rename log: {}", path.display()))?;
+    serde_json::from_str(&content).with_context(|| format!("Failed to parse rename log: {}", path.display()))
+}
+
+/// Write the rename log to the directory, or remove the file if the log is empty.
+fn write_rename_log_file(dir: &Path, log: &RenameLog) -> Result<()> {
+    let path = dir.join(RENAME_LOG);
+    if log.runs.is_empty() {
+        if path.is_file() {
+            fs::remove_file(&path).with_context(|| format!("Failed to remove rename log: {}", path.display()))?;
+        }
+        return Ok(());
+    }
+    let content = serde_json::to_string_pretty(log).context("Failed to serialize rename log")?;
+    fs::write(&path, content).with_context(|| format!("Failed to write rename log: {}", path.display()))
+}
+
+/// Revert the renames of the most recent run logged in the directory, newest rename first.
+/// Renames whose new path is gone or whose old path exists again are skipped.
+/// Skipped and failed renames stay in the log, and failed renames return an error.
+fn undo_renames(path: Option<&str>, dryrun: bool) -> Result<()> {
+    let mut log_dir = std::path::absolute(path.unwrap_or(".")).context("Failed to resolve directory")?;
+    let mut log = read_rename_log(&log_dir)?;
+    let entries = log
+        .runs
+        .pop()
+        .with_context(|| format!("No renames to undo in {}", log_dir.display()))?;
+
+    let mut num_reverted: usize = 0;
+    let mut skipped: Vec<(&RenameLogEntry, &str)> = Vec::new();
+    // Entries that were not reverted stay in the log so they can be retried
+    let mut remaining: Vec<RenameLogEntry> = Vec::new();
+    let mut num_failed: usize = 0;
+    for entry in entries.iter().rev() {
+        let old_str = cli_tools::path_to_string_relative(&entry.old);
+        let new_str = cli_tools::path_to_string_relative(&entry.new);
+        let capitalization_change_only = entry.old != entry.new && old_str.to_lowercase() == new_str.to_lowercase();
+        if !entry.new.exists() {
+            skipped.push((entry, "renamed path no longer exists"));
+            remaining.push(entry.clone());
+            continue;
+        }
+        if entry.old.exists() && !capitalization_change_only {
+            skipped.push((entry, "original path exists again"));
+            remaining.push(entry.clone());
+            continue;
+        }
+        if dryrun {
+            println!("{}", "Dryrun:".bold().cyan());
+        } else {
+            let result = if capitalization_change_only {
+                Dots::rename_with_temp_file(&RealFs, &entry.new, &entry.old)
+            } else {
+                RealFs.rename(&entry.new, &entry.old)
+            };
+            if let Err(e) = result {
+                eprintln!("{}", format!("Failed to revert {new_str}: {e:#}").red());
+                remaining.push(entry.clone());
+                num_failed += 1;
+                continue;
+            }
+            if entry.new == log_dir {
+                // The logged directory itself was renamed back
+                log_dir.clone_from(&entry.old);
+            }
+        }
+        cli_tools::show_path_diff(&new_str, &old_str);
+        num_reverted += 1;
+    }
+
+    if !skipped.is_empty() {
+        println!(
+            "{}",
+            format!("Skipped {}:", format_count(skipped.len(), "rename", "renames"))
+                .yellow()
+                .bold()
+        );
+        for (entry, reason) in &skipped {
+            println!(
+                "{}",
+                format!(
+                    "  {} -> {}: {reason}",
+                    cli_tools::path_to_string_relative(&entry.new),
+                    cli_tools::path_to_string_relative(&entry.old)
+                )
+                .yellow()
+            );
+        }
+    }
+    if dryrun {
+        println!(
+            "Dryrun: would have reverted {}",
+            format_count(num_reverted, "rename", "renames")
+        );
+    } else {
+        if !remaining.is_empty() {
+            remaining.reverse();
+            log.runs.push(remaining);
+        }
+        write_rename_log_file(&log_dir, &log)?;
+        println!(
+            "{}",
+            format!("Reverted {}", format_count(num_reverted, "rename", "renames")).green()
+        );
+        if num_failed > 0 {
+            anyhow::bail!("Failed to revert {}", format_count(num_failed, "rename", "renames"));
+        }
+    }
+    Ok(())
+}
+
+/// Print the stored original name for the given path.
+fn print_original_names(paths: &[String]) -> Result<()> {
+    let paths = cli_tools::resolve_input_paths(paths)?;
+    for path in &paths {
+        let relative_path = cli_tools::path_to_string_relative(path);
+        let name = read_original_name(path)?.with_context(|| format!("No original name stored for {relative_path}"))?;
+        if paths.len() > 1 {
+            println!("{relative_path}: {name}");
+        } else {
+            println!("{name}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn write_xattr_original(path: &Path, name: &str) -> std::io::Result<()> {
+    xattr::set(path, ORIGINAL_NAME_ATTRIBUTE, name.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_xattr_original(_path: &Path, _name: &str) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "extended attributes are not available on this platform",
+    ))
+}
+
+#[cfg(unix)]
+fn read_xattr_original(path: &Path) -> Option<String> {
+    xattr::get(path, ORIGINAL_NAME_ATTRIBUTE)
+        .ok()
+        .flatten()
+        .map(|value| String::from_utf8_lossy(&value).to_string())
+}
+
+#[cfg(not(unix))]
+const fn read_xattr_original(_path: &Path) -> Option<String> {
+    None
+}
+
+/// Get the original name for a path from the manifest file in its parent directory.
+/// The latest entry for the name is used.
+fn read_manifest_original(path: &Path) -> Result<Option<String>> {
+    let name = cli_tools::os_str_to_string(path.file_name().context("Failed to get file name")?);
+    let manifest = path
+        .parent()
+        .context("Failed to get parent dir")?
+        .join(ORIGINALS_MANIFEST);
+    if !manifest.is_file() {
+        return Ok(None);
+    }
+    let content =
+        fs::read_to_string(&manifest).with_context(|| format!("Failed to read manifest: {}", manifest.display()))?;
+    Ok(content
+        .lines()
+        .rev()
+        .filter_map(|line| line.split_once('\t'))
+        .find(|(new_name, _)| *new_name == name)
+        .map(|(_, original)| original.to_string()))
+}
+
+/// Append a line with the new name and original name to the manifest file in the parent directory.
+fn append_manifest_original(new_path: &Path, original_name: &str) -> Result<()> {
+    let new_name = cli_tools::os_str_to_string(new_path.file_name().context("Failed to get file name")?);
+    if [&new_name, original_name]
+        .iter()
+        .any(|name| name.contains(['\t', '\n']))
+    {
+        anyhow::bail!("Names with tabs or newlines can not be stored in the manifest");
+    }
+    let manifest = new_path
+        .parent()
+        .context("Failed to get parent dir")?
+        .join(ORIGINALS_MANIFEST);
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&manifest)
+        .with_context(|| format!("Failed to open manifest: {}", manifest.display()))?;
+    writeln!(file, "{new_name}\t{original_name}")?;
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let mut args = Args::parse();
+    match args.command.take() {
+        Some(Command::Check { paths }) => check_naming_policy(&paths),
+        Some(Command::Originals { paths }) => print_original_names(&paths),
+        Some(Command::Undo { path, print }) => undo_renames(path.as_deref(), print),
+        Some(Command::Format { input, pairs, dir_name }) => {
+            let content = if input == "-" {
+                io::read_to_string(io::stdin()).context("Failed to read names from stdin")?
+            } else {
+                fs::read_to_string(&input).with_context(|| format!("Failed to read names: {input}"))?
+            };
+            let mut dots = Dots::new(args)?;
+            dots.format_names(&content, &mut io::stdout().lock(), pairs, dir_name.as_deref())
+        }
+        None => {
+            if let Some(count) = args.bench_internal {
+                Dots::new(args)?.benchmark(count);
+                return Ok(());
+            }
+            Dots::run_with_args(args)
+        }
+    }
+}
+
+#[cfg(test)]
+mod dots_tests {
+    use super::*;
+
+    use std::time::SystemTime;
+
+    use cli_tools::fs_ops::FsOp;
+
+    static DOTS: LazyLock<Dots> = LazyLock::new(Dots::default);
+
+    #[test]
+    fn test_format_basic() {
+        assert_eq!(DOTS.format_name("Some file"), "Some.File");
+        assert_eq!(DOTS.format_name("some file"), "Some.File");
+        assert_eq!(DOTS.format_name("word"), "Word");
+        assert_eq!(DOTS.format_name("__word__"), "Word");
+    }
+
+    #[test]
+    fn test_format_name_with_newlines() {
+        assert_eq!(
+            DOTS.format_name("Meeting \tNotes \n(2023) - Draft\r\n"),
+            "Meeting.Notes.2023.Draft"
+        );
+    }
+
+    #[test]
+    fn test_format_name_no_brackets() {
+        assert_eq!(DOTS.format_name("John Doe - Document"), "John.Doe.Document");
+    }
+
+    #[test]
+    fn test_format_name_with_brackets() {
+        assert_eq!(
+            DOTS.format_name("Project Report - [Final Version]"),
+            "Project.Report.Final.Version"
+        );
+        assert_eq!(DOTS.format_name("Code {Snippet} (example)"), "Code.Snippet.Example");
+    }
+
+    #[test]
     fn test_format_name_with_parentheses() {
         assert_eq!(
-            DOTS.format_name("Meeting Notes (2023) - Draft"),
-            "Meeting.Notes.2023.Draft"
+            DOTS.format_name("Meeting Notes (2023) - Draft"),
+            "Meeting.Notes.2023.Draft"
+        );
+    }
+
+    #[test]
+    fn test_format_name_with_duplicate_years() {
+        assert_eq!(DOTS.format_name("Movie (2008) 2008 Remaster"), "Movie.2008.Remaster");
+        assert_eq!(
+            DOTS.format_name("Movie Name [2008] (2008) 1080p"),
+            "Movie.Name.2008.1080p"
+        );
+        assert_eq!(DOTS.format_name("Movie Name (2008) 1080p"), "Movie.Name.2008.1080p");
+        assert_eq!(DOTS.format_name("Blade Runner 2049 (2017)"), "Blade.Runner.2049.2017");
+        assert_eq!(
+            DOTS.format_name("2001 A Space Odyssey (1968)"),
+            "2001.a.Space.Odyssey.1968"
+        );
+        assert_eq!(
+            DOTS.format_name("Year 2012 Special Edition Of The Movie 2012"),
+            "Year.2012.Special.Edition.of.the.Movie.2012"
+        );
+    }
+
+    #[test]
+    fn test_format_name_with_year_brackets() {
+        let dots = Dots {
+            config: Config {
+                year_brackets: true,
+                ..Config::default()
+            },
+            ..Dots::default()
+        };
+        assert_eq!(dots.format_name("Movie Name (2008)"), "Movie.Name.(2008)");
+        assert_eq!(dots.format_name("Movie Name [2008] 1080p"), "Movie.Name.(2008).1080p");
+        assert_eq!(dots.format_name("Movie (2008) 2008 Remaster"), "Movie.(2008).Remaster");
+        assert_eq!(dots.format_name("Blade Runner 2049 (2017)"), "Blade.Runner.2049.(2017)");
+        assert_eq!(dots.format_name("Movie Name 2008 1080p"), "Movie.Name.2008.1080p");
+        assert_eq!(dots.format_name("Movie.Name.(2008)"), "Movie.Name.(2008)");
+    }
+
+    #[test]
+    fn test_format_names_from_input() {
+        let mut dots = Dots {
+            config: Config {
+                replace: vec![("Draft".to_string(), "Final".to_string())],
+                ..Config::default()
+            },
+            ..Dots::default()
+        };
+        let mut output = Vec::new();
+        dots.format_names(
+            "some file.TXT\r\n\nmovies/My Movie (2008) Draft.mkv\n",
+            &mut output,
+            false,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Some.File.txt\nmovies/My.Movie.2008.Final.mkv\n"
+        );
+
+        let mut output = Vec::new();
+        dots.format_names("some file.txt\nREADME\n", &mut output, true, None)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "some file.txt\tSome.File.txt\nREADME\tReadme\n"
+        );
+    }
+
+    #[test]
+    fn test_format_names_with_prefix_dir() {
+        let mut dots = Dots {
+            config: Config {
+                prefix_dir: true,
+                ..Config::default()
+            },
+            ..Dots::default()
+        };
+        let mut output = Vec::new();
+        assert!(dots.format_names("some file.txt", &mut output, false, None).is_err());
+        dots.format_names("some file.txt", &mut output, false, Some("my dir"))
+            .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "My.Dir.Some.File.txt\n");
+    }
+
+    fn reorder_dots(order: Option<&[String]>) -> Dots {
+        Dots {
+            config: Config {
+                token_order: Some(TokenClass::parse_order(order).unwrap()),
+                ..Config::default()
+            },
+            ..Dots::default()
+        }
+    }
+
+    #[test]
+    fn test_classify_tokens() {
+        let parts = [
+            "2001", "a", "Space", "Odyssey", "1968", "S01E02", "2160p", "WEB", "DL", "HEVC",
+        ];
+        let classes: Vec<TokenClass> = classify_tokens(&parts).into_iter().map(|(class, _)| class).collect();
+        assert_eq!(
+            classes,
+            vec![
+                TokenClass::Title,
+                TokenClass::Title,
+                TokenClass::Title,
+                TokenClass::Title,
+                TokenClass::Year,
+                TokenClass::Episode,
+                TokenClass::Resolution,
+                TokenClass::Source,
+                TokenClass::Source,
+                TokenClass::Codec,
+            ]
+        );
+        // Suffix tags only belong to a preceding source tag
+        assert_eq!(classify_tokens(&["DL", "Show"])[0].0, TokenClass::Title);
+        assert_eq!(classify_tokens(&["1920x1080"])[0].0, TokenClass::Resolution);
+    }
+
+    #[test]
+    fn test_reorder_tokens() {
+        let dots = reorder_dots(None);
+        assert_eq!(
+            dots.format_name("x265 1080p Movie Name 2008"),
+            "Movie.Name.2008.1080p.x265"
+        );
+        assert_eq!(
+            dots.format_name("Show S01E02 WEB-DL 1080p x265"),
+            "Show.S01E02.1080p.WEB.DL.x265"
+        );
+        assert_eq!(
+            dots.format_name("2001 A Space Odyssey HEVC 2160p 1968"),
+            "2001.a.Space.Odyssey.1968.2160p.HEVC"
+        );
+        // Missing token classes
+        assert_eq!(dots.format_name("Show 720p S02E10"), "Show.S02E10.720p");
+        assert_eq!(dots.format_name("Documentary"), "Documentary");
+        assert_eq!(dots.format_name("BluRay Some Movie"), "Some.Movie.BluRay");
+    }
+
+    #[test]
+    fn test_reorder_tokens_is_idempotent() {
+        let dots = reorder_dots(None);
+        for name in [
+            "Movie.Name.2008.1080p.BluRay.x264",
+            "Show.S01E02.1080p.WEB.DL.x265",
+            "Blade.Runner.2049.2017.2160p",
+            "Title.Only",
+        ] {
+            assert_eq!(dots.format_name(name), name);
+        }
+    }
+
+    #[test]
+    fn test_custom_token_order() {
+        let order = ["resolution", "title", "Codec"].map(String::from);
+        let dots = reorder_dots(Some(&order));
+        assert_eq!(
+            dots.format_name("Movie Name 2008 x264 1080p BluRay"),
+            "1080p.Movie.Name.x264.2008.BluRay"
+        );
+    }
+
+    #[test]
+    fn test_invalid_token_order() {
+        assert!(TokenClass::parse_order(Some(&["title".to_string(), "quality".to_string()])).is_err());
+        assert!(TokenClass::parse_order(Some(&["year".to_string(), "year".to_string()])).is_err());
+        assert_eq!(TokenClass::parse_order(None).unwrap(), DEFAULT_TOKEN_ORDER.to_vec());
+    }
+
+    #[test]
+    fn test_format_name_with_extra_dots() {
+        assert_eq!(DOTS.format_name("file..with...dots"), "File.With.Dots");
+        assert_eq!(
+            DOTS.format_name("...leading.and.trailing.dots..."),
+            "Leading.and.Trailing.Dots"
+        );
+    }
+
+    #[test]
+    fn test_format_name_with_exclamations() {
+        assert_eq!(DOTS.format_name("Exciting!Document!!"), "Exciting.Document");
+        assert_eq!(DOTS.format_name("Hello!!!World!!"), "Hello.World");
+    }
+
+    #[test]
+    fn test_format_name_with_dotcom() {
+        assert_eq!(
+            DOTS.format_name("visit.website.com.for.details"),
+            "Visit.Website.for.Details"
+        );
+        assert_eq!(
+            DOTS.format_name("Contact us at email@domain.net"),
+            "Contact.Us.at.Email.Domain"
+        );
+        assert_eq!(DOTS.format_name("Contact.company.test"), "Contact.Company.Test");
+    }
+
+    #[test]
+    fn test_format_name_with_combined_cases() {
+        assert_eq!(
+            DOTS.format_name("Amazing [Stuff]!! Visit my.site.com..now"),
+            "Amazing.Stuff.Visit.My.Site.Now"
+        );
+    }
+
+    #[test]
+    fn test_format_name_with_weird_characters() {
+        assert_eq!(
+            DOTS.format_name("Weird-Text-~File-Name-@Example#"),
+            "Weird.Text.File.Name.Example"
+        );
+    }
+
+    #[test]
+    fn test_format_name_empty_string() {
+        assert_eq!(DOTS.format_name(""), "");
+    }
+
+    #[test]
+    fn test_format_name_no_changes() {
+        assert_eq!(DOTS.format_name("SingleWord"), "SingleWord");
+    }
+
+    #[test]
+    fn test_move_to_start() {
+        let mut dots = Dots::default();
+        dots.config.move_to_start = vec!["Test".to_string()];
+        assert_eq!(dots.format_name("This is a test string test"), "Test.This.Is.a.String");
+        assert_eq!(
+            dots.format_name("Test.This.Is.a.test.string.test"),
+            "Test.This.Is.a.String"
+        );
+        assert_eq!(dots.format_name("test"), "Test");
+        assert_eq!(dots.format_name("Test"), "Test");
+    }
+
+    #[test]
+    fn test_move_to_end() {
+        let mut dots = Dots::default();
+        dots.config.move_to_end = vec!["Test".to_string()];
+        assert_eq!(dots.format_name("This is a test string test"), "This.Is.a.String.Test");
+        assert_eq!(
+            dots.format_name("Test.This.Is.a.test.string.test"),
+            "This.Is.a.String.Test"
+        );
+        assert_eq!(dots.format_name("test"), "Test");
+        assert_eq!(dots.format_name("Test"), "Test");
+    }
+
+    #[test]
+    fn test_final_cleanup() {
+        let cases = [
+            ("Name..Final.", "Name.Final"),
+            ("Name.-.Thing", "Name.Thing"),
+            ("Name._.Thing", "Name.Thing"),
+            ("Name-.Thing", "Name.Thing"),
+            ("Name._Thing", "Name.Thing"),
+            ("-Name.Thing_", "Name.Thing"),
+            ("._-Name-_.", "Name"),
+            ("Name.().Thing", "Name.Thing"),
+            ("Name.[.].Thing", "Name.Thing"),
+            ("Name.{ }", "Name"),
+            ("Name.([])", "Name"),
+            ("Name.(Live).Thing", "Name.(Live).Thing"),
+            ("Jay-Z.Some_Thing", "Jay-Z.Some_Thing"),
+            ("Name", "Name"),
+            ("", ""),
+        ];
+        for (name, expected) in cases {
+            let mut cleaned = name.to_string();
+            final_cleanup(&mut cleaned);
+            assert_eq!(cleaned, expected, "{name}");
+            final_cleanup(&mut cleaned);
+            assert_eq!(cleaned, expected, "not idempotent: {name}");
+        }
+    }
+
+    #[test]
+    fn test_final_cleanup_after_replacements() {
+        let mut dots = Dots {
+            config: Config {
+                replace: vec![("Remove".to_string(), "-".to_string())],
+                ..Config::default()
+            },
+            ..Dots::default()
+        };
+        assert_eq!(dots.format_name("Name Remove Thing"), "Name.Thing");
+        dots.config.no_final_cleanup = true;
+        assert_eq!(dots.format_name("Name Remove Thing"), "Name.-.Thing");
+    }
+
+    #[test]
+    fn test_remove_identifier() {
+        let dots = Dots::default();
+        assert_eq!(
+            dots.format_name("This is a string test ^[640e54a564228]"),
+            "This.Is.a.String.Test"
+        );
+        assert_eq!(
+            dots.format_name("This.Is.a.test.string.65f09e4248e03..."),
+            "This.Is.a.Test.String"
+        );
+        assert_eq!(dots.format_name("test Ph5d9473a841fe9"), "Test");
+        assert_eq!(dots.format_name("Test-355989849"), "Test");
+    }
+
+    #[test]
+    fn test_normalization_conflicts() {
+        let nfc = PathBuf::from("dir").join("\u{00C5}land.txt");
+        let nfd = PathBuf::from("dir").join("A\u{030A}land.txt");
+        assert_ne!(nfc, nfd);
+
+        let conflicts = find_normalization_conflicts(&[nfd.clone(), nfc.clone()]);
+        assert_eq!(conflicts, vec![(nfc.clone(), vec![nfd.clone()])]);
+
+        let other_dir = PathBuf::from("other").join("A\u{030A}land.txt");
+        assert!(find_normalization_conflicts(&[nfc, other_dir]).is_empty());
+        assert!(find_normalization_conflicts(&[nfd]).is_empty());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_normalization_conflicts_are_skipped_or_deduped() {
+        let dir = tempfile::Builder::new().prefix("dots").tempdir().unwrap();
+        let nfc = dir.path().join("\u{00C5}land file.txt");
+        let nfd = dir.path().join("A\u{030A}land file.txt");
+        fs::File::create(&nfc).unwrap();
+        fs::File::create(&nfd).unwrap();
+
+        let mut dots = Dots {
+            root: dir.path().to_path_buf(),
+            ..Dots::default()
+        };
+        assert!(dots.gather_files_to_rename().unwrap().is_empty());
+
+        dots.config.dedupe_normalization = true;
+        let renames = dots.gather_files_to_rename().unwrap();
+        assert_eq!(
+            renames,
+            vec![
+                (nfd, dir.path().join("\u{00C5}land file.nfd.txt")),
+                (nfc, dir.path().join("\u{00C5}land.File.txt")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rename_nested_directories_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Root");
+        let nested = root.join("foo_bar").join("baz_qux");
+        fs::create_dir_all(&nested).unwrap();
+        fs::File::create(nested.join("some_file.txt")).unwrap();
+
+        let mut dots = Dots {
+            root: root.clone(),
+            config: Config {
+                recursive: true,
+                target: RenameTarget::Dirs,
+                ..Config::default()
+            },
+            ..Dots::default()
+        };
+        dots.run().unwrap();
+
+        let renamed = root.join("Foo Bar").join("Baz Qux");
+        assert!(renamed.is_dir());
+        assert!(renamed.join("some_file.txt").is_file());
+        assert!(!root.join("foo_bar").exists());
+    }
+
+    #[test]
+    fn test_rename_paths_rebases_pending_children() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Root");
+        let parent = root.join("foo_bar");
+        let child = parent.join("baz_qux");
+        fs::create_dir_all(&child).unwrap();
+
+        let mut dots = Dots {
+            root: root.clone(),
+            ..Dots::default()
+        };
+        // Parent before child so the child path has to be recomputed after the parent rename
+        let renames = vec![
+            (parent, root.join("Foo Bar")),
+            (child.clone(), child.with_file_name("Baz Qux")),
+        ];
+        assert_eq!(dots.rename_paths(renames), 2);
+        assert!(root.join("Foo Bar").join("Baz Qux").is_dir());
+    }
+
+    #[test]
+    fn test_sort_order_changes() {
+        let names: Vec<String> = ["Show E9.mkv", "Show E10.mkv", "Other.mkv"]
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        let padding = HashMap::from([
+            ("Show E9.mkv".to_string(), "Show.E09.mkv".to_string()),
+            ("Show E10.mkv".to_string(), "Show.E10.mkv".to_string()),
+        ]);
+        assert!(sort_order_changes(&names, &padding).is_empty());
+
+        let prefix = HashMap::from([("Show E9.mkv".to_string(), "A.Show.E9.mkv".to_string())]);
+        assert_eq!(sort_order_changes(&names, &prefix), vec!["Show E9.mkv".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_file_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("sub");
+        fs::create_dir(&nested).unwrap();
+        fs::File::create(dir.path().join("first file.txt")).unwrap();
+        fs::File::create(nested.join("second file.txt")).unwrap();
+        let absolute = nested.join("second file.txt");
+
+        let content = format!("first file.txt\r\n\nmissing.txt\n{}\nsub\n", absolute.display());
+        let (files, missing) = parse_file_list(&content, dir.path());
+        assert_eq!(files, vec![dir.path().join("first file.txt"), absolute]);
+        assert_eq!(missing, vec![(3, "missing.txt".to_string()), (5, "sub".to_string())]);
+    }
+
+    #[test]
+    fn test_files_from_only_processes_listed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Root");
+        fs::create_dir(&root).unwrap();
+        fs::File::create(root.join("listed file.txt")).unwrap();
+        fs::File::create(root.join("other file.txt")).unwrap();
+        let list = dir.path().join("list.txt");
+        fs::write(&list, "listed file.txt\nmissing file.txt\n").unwrap();
+
+        let mut dots = Dots {
+            root: root.clone(),
+            config: Config {
+                files_from: Some(list.to_string_lossy().to_string()),
+                ..Config::default()
+            },
+            ..Dots::default()
+        };
+        let renames = dots.gather_files_to_rename().unwrap();
+        assert_eq!(
+            renames,
+            vec![(root.join("listed file.txt"), root.join("Listed.File.txt"))]
         );
+        assert_eq!(dots.num_missing, 1);
+        assert!(dots.check_missing().is_err());
+
+        dots.config.ignore_missing = true;
+        assert!(dots.check_missing().is_ok());
     }
 
     #[test]
-    fn test_format_name_with_extra_dots() {
-        assert_eq!(DOTS.format_name("file..with...dots"), "File.With.Dots");
+    fn test_files_from_applies_filters() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Root");
+        fs::create_dir_all(root.join("skip dir")).unwrap();
+        fs::write(root.join("small file.txt"), "small").unwrap();
+        fs::write(root.join("large file.txt"), vec![0_u8; 2048]).unwrap();
+        fs::write(root.join("skip dir").join("large nested.txt"), vec![0_u8; 2048]).unwrap();
+        fs::write(root.join("large file.log"), vec![0_u8; 2048]).unwrap();
+        let list = dir.path().join("list.txt");
+        fs::write(
+            &list,
+            "small file.txt\nlarge file.txt\nskip dir/large nested.txt\nlarge file.log\n",
+        )
+        .unwrap();
+
+        let mut dots = Dots {
+            root: root.clone(),
+            config: Config {
+                files_from: Some(list.to_string_lossy().to_string()),
+                min_size: Some(1024),
+                skip_rules: cli_tools::SkipRules {
+                    skip_extensions: vec!["log".to_string()],
+                    exclude_dirs: vec!["skip".to_string()],
+                    ..cli_tools::SkipRules::default()
+                },
+                ..Config::default()
+            },
+            ..Dots::default()
+        };
+        let renames = dots.gather_files_to_rename().unwrap();
         assert_eq!(
-            DOTS.format_name("...leading.and.trailing.dots..."),
-            "Leading.and.Trailing.Dots"
+            renames,
+            vec![(root.join("large file.txt"), root.join("Large.File.txt"))]
         );
+        assert_eq!(dots.num_size_filtered, 1);
+        assert_eq!(dots.num_missing, 0);
     }
 
     #[test]
-    fn test_format_name_with_exclamations() {
-        assert_eq!(DOTS.format_name("Exciting!Document!!"), "Exciting.Document");
-        assert_eq!(DOTS.format_name("Hello!!!World!!"), "Hello.World");
+    fn test_preserve_original_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Root");
+        fs::create_dir(&root).unwrap();
+        fs::File::create(root.join("some file.txt")).unwrap();
+
+        let mut dots = Dots {
+            root: root.clone(),
+            config: Config {
+                preserve_original: Some(PreserveMode::Manifest),
+                ..Config::default()
+            },
+            ..Dots::default()
+        };
+        dots.run().unwrap();
+
+        let renamed = root.join("Some.File.txt");
+        assert!(renamed.is_file());
+        assert_eq!(
+            fs::read_to_string(root.join(ORIGINALS_MANIFEST)).unwrap(),
+            "Some.File.txt\tsome file.txt\n"
+        );
+        assert_eq!(
+            read_manifest_original(&renamed).unwrap(),
+            Some("some file.txt".to_string())
+        );
+
+        // Renaming again keeps the first original name
+        let second = root.join("Second.txt");
+        fs::rename(&renamed, &second).unwrap();
+        store_original_name(PreserveMode::Manifest, &renamed, &second).unwrap();
+        assert_eq!(read_original_name(&second).unwrap(), Some("some file.txt".to_string()));
     }
 
     #[test]
-    fn test_format_name_with_dotcom() {
+    fn test_preserve_original_xattr_or_fallback() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("old name.txt");
+        let new_path = dir.path().join("Old.Name.txt");
+        fs::File::create(&path).unwrap();
+        fs::rename(&path, &new_path).unwrap();
+
+        store_original_name(PreserveMode::Xattr, &path, &new_path).unwrap();
+        assert_eq!(read_original_name(&new_path).unwrap(), Some("old name.txt".to_string()));
+    }
+
+    #[test]
+    fn test_date_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Root");
+        fs::create_dir(&root).unwrap();
+        fs::File::create(root.join("photo 2023-12-24.jpg")).unwrap();
+        fs::File::create(root.join("photo without date.jpg")).unwrap();
+        fs::File::create(root.join("another photo.jpg")).unwrap();
+
+        let mut dots = Dots {
+            root: root.clone(),
+            config: Config {
+                date_filter: Some(DateFilter::WithDates),
+                ..Config::default()
+            },
+            ..Dots::default()
+        };
+        let renames = dots.gather_files_to_rename().unwrap();
         assert_eq!(
-            DOTS.format_name("visit.website.com.for.details"),
-            "Visit.Website.for.Details"
+            renames,
+            vec![(root.join("photo 2023-12-24.jpg"), root.join("Photo.2023.12.24.jpg"))]
         );
+        assert_eq!(dots.date_counts, Some((1, 2)));
+
+        dots.config.date_filter = Some(DateFilter::WithoutDates);
+        let renames = dots.gather_files_to_rename().unwrap();
+        assert_eq!(renames.len(), 2);
+        assert_eq!(dots.date_counts, Some((1, 2)));
+    }
+
+    #[test]
+    fn test_size_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Root");
+        fs::create_dir(&root).unwrap();
+        fs::write(root.join("tiny file.nfo"), "x").unwrap();
+        fs::write(root.join("medium file.mkv"), vec![0_u8; 2048]).unwrap();
+        fs::write(root.join("large file.iso"), vec![0_u8; 8192]).unwrap();
+
+        let mut dots = Dots {
+            root: root.clone(),
+            config: Config {
+                min_size: Some(1024),
+                max_size: Some(4096),
+                ..Config::default()
+            },
+            ..Dots::default()
+        };
+        let renames = dots.gather_files_to_rename().unwrap();
         assert_eq!(
-            DOTS.format_name("Contact us at email@domain.net"),
-            "Contact.Us.at.Email.Domain"
+            renames,
+            vec![(root.join("medium file.mkv"), root.join("Medium.File.mkv"))]
         );
-        assert_eq!(DOTS.format_name("Contact.company.test"), "Contact.Company.Test");
+        assert_eq!(dots.num_size_filtered, 2);
+
+        // Size filters combine with the extension skip rules
+        dots.config.max_size = None;
+        dots.config.skip_rules.skip_extensions = vec!["iso".to_string()];
+        let renames = dots.gather_files_to_rename().unwrap();
+        assert_eq!(renames.len(), 1);
+        assert_eq!(dots.num_size_filtered, 1);
     }
 
     #[test]
-    fn test_format_name_with_combined_cases() {
+    fn test_extension_rules() {
+        let rules: DotsConfig = toml::from_str(&format!(
+            r#"
+            [extensions.srt]
+            regex_replace = [["\\.En$", ".en"]]
+
+            [extensions."jpg, png"]
+            prefix = "{DATE_PLACEHOLDER}"
+            cleanup = false
+            "#
+        ))
+        .unwrap();
+        let extension_rules = Config::compile_extension_rules(rules.extensions).unwrap();
+        assert_eq!(extension_rules.len(), 3);
+        assert_eq!(extension_rules["png"].name, "jpg, png");
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Root");
+        fs::create_dir(&root).unwrap();
+        for name in ["movie 640e54a564228.mp4", "movie.en.srt", "holiday 640e54a564228.JPG"] {
+            fs::File::create(root.join(name)).unwrap();
+        }
+        let modified = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        fs::File::options()
+            .write(true)
+            .open(root.join("holiday 640e54a564228.JPG"))
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let date = chrono::DateTime::<chrono::Local>::from(modified)
+            .format("%Y.%m.%d")
+            .to_string();
+
+        let mut dots = Dots {
+            root: root.clone(),
+            config: Config {
+                extension_rules,
+                ..Config::default()
+            },
+            ..Dots::default()
+        };
+        let renames = dots.gather_files_to_rename().unwrap();
         assert_eq!(
-            DOTS.format_name("Amazing [Stuff]!! Visit my.site.com..now"),
-            "Amazing.Stuff.Visit.My.Site.Now"
+            renames,
+            vec![
+                (
+                    root.join("holiday 640e54a564228.JPG"),
+                    root.join(format!("{date}.Holiday.640e54a564228.jpg"))
+                ),
+                (root.join("movie 640e54a564228.mp4"), root.join("Movie.mp4")),
+                (root.join("movie.en.srt"), root.join("Movie.en.srt")),
+            ]
         );
+
+        // Prefix from the command line wins over the extension rule
+        dots.config.prefix = Some("Trip".to_string());
+        let renames = dots.gather_files_to_rename().unwrap();
+        assert_eq!(renames[0].1, root.join("Trip.Holiday.640e54a564228.jpg"));
     }
 
     #[test]
-    fn test_format_name_with_weird_characters() {
+    fn test_extension_in_multiple_rules() {
+        let rules: DotsConfig = toml::from_str("[extensions.jpg]\n[extensions.\"png,JPG\"]\n").unwrap();
+        assert!(Config::compile_extension_rules(rules.extensions).is_err());
+    }
+
+    #[test]
+    fn test_exclude_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Root");
+        fs::create_dir_all(root.join("skip me")).unwrap();
+        fs::File::create(root.join("skip me").join("nested file.txt")).unwrap();
+        fs::File::create(root.join("skip me too.txt")).unwrap();
+        fs::File::create(root.join("some file.txt")).unwrap();
+
+        let mut dots = Dots {
+            root: root.clone(),
+            config: Config {
+                recursive: true,
+                skip_rules: cli_tools::SkipRules {
+                    exclude_dirs: vec!["skip".to_string()],
+                    ..cli_tools::SkipRules::default()
+                },
+                ..Config::default()
+            },
+            ..Dots::default()
+        };
+        let names = |renames: Vec<(PathBuf, PathBuf)>| {
+            renames
+                .into_iter()
+                .map(|(_, new_path)| cli_tools::get_relative_path_or_filename(&new_path, &root))
+                .collect::<Vec<_>>()
+        };
         assert_eq!(
-            DOTS.format_name("Weird-Text-~File-Name-@Example#"),
-            "Weird.Text.File.Name.Example"
+            names(dots.gather_files_to_rename().unwrap()),
+            vec!["Skip.Me.Too.txt", "Some.File.txt"]
         );
+
+        dots.config.skip_rules.exclude = vec!["skip".to_string()];
+        assert_eq!(names(dots.gather_files_to_rename().unwrap()), vec!["Some.File.txt"]);
     }
 
     #[test]
-    fn test_format_name_empty_string() {
-        assert_eq!(DOTS.format_name(""), "");
+    fn test_exclude_patterns_do_not_match_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("samples");
+        fs::create_dir_all(root.join("sample dir")).unwrap();
+        fs::File::create(root.join("some file.txt")).unwrap();
+
+        let mut dots = Dots {
+            root: root.clone(),
+            config: Config {
+                skip_rules: cli_tools::SkipRules {
+                    exclude: vec!["sample".to_string()],
+                    ..cli_tools::SkipRules::default()
+                },
+                ..Config::default()
+            },
+            ..Dots::default()
+        };
+        assert_eq!(
+            dots.gather_files_to_rename().unwrap(),
+            vec![(root.join("some file.txt"), root.join("Some.File.txt"))]
+        );
+
+        dots.config.skip_rules = cli_tools::SkipRules {
+            exclude_dirs: vec!["sample".to_string()],
+            ..cli_tools::SkipRules::default()
+        };
+        assert_eq!(
+            dots.gather_directories_to_rename(),
+            vec![(root, dir.path().join("Samples"))]
+        );
     }
 
     #[test]
-    fn test_format_name_no_changes() {
-        assert_eq!(DOTS.format_name("SingleWord"), "SingleWord");
+    fn test_naming_policy_rules() {
+        let policy = NamingPolicy {
+            allowed_characters: Some(Regex::new("^(?:[A-Za-z0-9.])+$").unwrap()),
+            ..NamingPolicy::default()
+        };
+        assert!(policy.check(Path::new("Some.File.txt")).is_empty());
+        assert_eq!(policy.check(Path::new("Some_File.txt")), vec![PolicyRule::Characters]);
+
+        let policy = NamingPolicy {
+            max_length: Some(8),
+            ..NamingPolicy::default()
+        };
+        assert!(policy.check(Path::new("Name.txt")).is_empty());
+        assert_eq!(policy.check(Path::new("Name1.txt")), vec![PolicyRule::Length]);
+
+        let policy = NamingPolicy {
+            no_spaces: true,
+            ..NamingPolicy::default()
+        };
+        assert_eq!(policy.check(Path::new("Some File.txt")), vec![PolicyRule::Spaces]);
+
+        let policy = NamingPolicy {
+            dot_separated: true,
+            ..NamingPolicy::default()
+        };
+        assert!(policy.check(Path::new("Some.File.txt")).is_empty());
+        assert_eq!(policy.check(Path::new("Some..File.txt")), vec![PolicyRule::Structure]);
+        assert_eq!(policy.check(Path::new("Some.File..txt")), vec![PolicyRule::Structure]);
+
+        let policy = NamingPolicy {
+            extensions: vec![(
+                Regex::new("^Music(/|$)").unwrap(),
+                vec!["mp3".to_string(), "flac".to_string()],
+            )],
+            ..NamingPolicy::default()
+        };
+        assert!(policy.check(Path::new("Music/Album/Song.FLAC")).is_empty());
+        assert!(policy.check(Path::new("Video/Song.txt")).is_empty());
+        assert_eq!(
+            policy.check(Path::new("Music/Album/Cover.jpg")),
+            vec![PolicyRule::Extension]
+        );
     }
 
     #[test]
-    fn test_move_to_start() {
-        let mut dots = Dots::default();
-        dots.config.move_to_start = vec!["Test".to_string()];
-        assert_eq!(dots.format_name("This is a test string test"), "Test.This.Is.a.String");
+    fn test_naming_policy_violations() {
+        let config: DotsConfig = toml::from_str(
+            r#"
+            [policy]
+            allowed_characters = "[A-Za-z0-9.]"
+            max_length = 20
+            dot_separated = true
+            no_spaces = true
+
+            [[policy.extensions]]
+            directory = "^Music"
+            allowed = ["mp3"]
+            "#,
+        )
+        .unwrap();
+        let policy = NamingPolicy::from_config(config.policy.as_ref().unwrap()).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Root");
+        fs::create_dir_all(root.join("Music")).unwrap();
+        for name in [
+            "Good.Name.txt",
+            "Bad Name.txt",
+            "Way.Too.Long.File.Name.txt",
+            "Music/Song.mp3",
+            "Music/Cover.jpg",
+            ".hidden file",
+        ] {
+            fs::File::create(root.join(name)).unwrap();
+        }
+
+        let violations = find_policy_violations(&root, &policy, &cli_tools::SkipRules::default());
         assert_eq!(
-            dots.format_name("Test.This.Is.a.test.string.test"),
-            "Test.This.Is.a.String"
+            violations,
+            BTreeMap::from([
+                (PolicyRule::Characters, vec![PathBuf::from("Bad Name.txt")]),
+                (PolicyRule::Length, vec![PathBuf::from("Way.Too.Long.File.Name.txt")]),
+                (PolicyRule::Spaces, vec![PathBuf::from("Bad Name.txt")]),
+                (PolicyRule::Extension, vec![PathBuf::from("Music/Cover.jpg")]),
+            ])
+        );
+
+        let violations = find_policy_violations(&root.join("Bad Name.txt"), &policy, &cli_tools::SkipRules::default());
+        assert_eq!(
+            violations,
+            BTreeMap::from([
+                (PolicyRule::Characters, vec![PathBuf::from("Bad Name.txt")]),
+                (PolicyRule::Spaces, vec![PathBuf::from("Bad Name.txt")]),
+            ])
         );
-        assert_eq!(dots.format_name("test"), "Test");
-        assert_eq!(dots.format_name("Test"), "Test");
     }
 
     #[test]
-    fn test_move_to_end() {
-        let mut dots = Dots::default();
-        dots.config.move_to_end = vec!["Test".to_string()];
-        assert_eq!(dots.format_name("This is a test string test"), "This.Is.a.String.Test");
+    fn test_dedupe_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Root");
+        let nested = root.join("Nested");
+        let other = dir.path().join("Other");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir(&other).unwrap();
+        fs::File::create(root.join("file.txt")).unwrap();
+        fs::File::create(nested.join("nested.txt")).unwrap();
+
+        let roots = vec![
+            nested.clone(),
+            root.clone(),
+            other.clone(),
+            root.join("file.txt"),
+            nested.join("nested.txt"),
+        ];
+        assert_eq!(dedupe_roots(&roots, true), vec![root.clone(), other.clone()]);
+        assert_eq!(dedupe_roots(&roots, false), vec![nested, root, other]);
+    }
+
+    #[test]
+    fn test_recursive_from_config_dedupes_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Root");
+        let nested = root.join("Nested");
+        fs::create_dir_all(&nested).unwrap();
+
+        let config = Config {
+            recursive: true,
+            ..Config::default()
+        };
+        let dots = Dots::with_config(&[nested.clone(), root.clone()], config).unwrap();
+        assert_eq!(dots.roots, vec![root.clone()]);
+        assert_eq!(dots.root, root);
+
+        let dots = Dots::with_config(&[nested.clone(), root.clone()], Config::default()).unwrap();
+        assert_eq!(dots.roots, vec![nested, root]);
+    }
+
+    #[test]
+    fn test_run_multiple_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("First");
+        let second = dir.path().join("Second");
+        fs::create_dir_all(first.join("nested dir")).unwrap();
+        fs::create_dir(&second).unwrap();
+        fs::File::create(first.join("first file.txt")).unwrap();
+        fs::File::create(first.join("nested dir").join("nested file.txt")).unwrap();
+        fs::File::create(second.join("second file.txt")).unwrap();
+
+        let roots = dedupe_roots(&[first.clone(), first.join("nested dir"), second.clone()], true);
+        let mut dots = Dots {
+            roots,
+            config: Config {
+                recursive: true,
+                ..Config::default()
+            },
+            ..Dots::default()
+        };
+        dots.run().unwrap();
+        assert!(first.join("First.File.txt").exists());
+        assert!(first.join("nested dir").join("Nested.File.txt").exists());
+        assert!(second.join("Second.File.txt").exists());
+        assert!(dots.failed.is_empty());
+    }
+
+    #[test]
+    fn test_locked_root_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("First");
+        let second = dir.path().join("Second");
+        fs::create_dir(&first).unwrap();
+        fs::create_dir(&second).unwrap();
+        fs::File::create(first.join("first file.txt")).unwrap();
+        fs::File::create(second.join("second file.txt")).unwrap();
+
+        let lock = cli_tools::lock::acquire_dir_lock(&first, "other", Duration::ZERO)
+            .unwrap()
+            .unwrap();
+        let mut dots = Dots {
+            roots: vec![first.clone(), second.clone()],
+            ..Dots::default()
+        };
+        dots.run().unwrap();
+        assert!(first.join("first file.txt").exists());
+        assert!(second.join("Second.File.txt").exists());
+        assert!(!second.join(cli_tools::lock::LOCK_FILE_NAME).exists());
+        drop(lock);
+    }
+
+    #[test]
+    fn test_select_duplicate_to_trash() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("My Movie.mkv");
+        let existing = dir.path().join("My.Movie.mkv");
+        fs::write(&path, "same content").unwrap();
+        fs::write(&existing, "same content").unwrap();
+        let older = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let newer = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_001);
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(newer)
+            .unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&existing)
+            .unwrap()
+            .set_modified(older)
+            .unwrap();
+
+        for compare_contents in [false, true] {
+            assert_eq!(
+                select_duplicate_to_trash(&path, &existing, DedupeKeep::Newer, compare_contents).unwrap(),
+                Some(existing.clone())
+            );
+            assert_eq!(
+                select_duplicate_to_trash(&path, &existing, DedupeKeep::Older, compare_contents).unwrap(),
+                Some(path.clone())
+            );
+        }
+    }
+
+    #[test]
+    fn test_select_duplicate_to_trash_different_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("My Movie.mkv");
+        let existing = dir.path().join("My.Movie.mkv");
+        fs::write(&path, "some content").unwrap();
+        fs::write(&existing, "different content").unwrap();
         assert_eq!(
-            dots.format_name("Test.This.Is.a.test.string.test"),
-            "This.Is.a.String.Test"
+            select_duplicate_to_trash(&path, &existing, DedupeKeep::Newer, false).unwrap(),
+            None
+        );
+
+        // Same size but different contents are only detected when comparing contents
+        fs::write(&existing, "same content").unwrap();
+        fs::write(&path, "some content").unwrap();
+        assert!(files_are_identical(&path, &existing, false).unwrap());
+        assert!(!files_are_identical(&path, &existing, true).unwrap());
+        assert_eq!(
+            select_duplicate_to_trash(&path, &existing, DedupeKeep::Newer, true).unwrap(),
+            None
         );
-        assert_eq!(dots.format_name("test"), "Test");
-        assert_eq!(dots.format_name("Test"), "Test");
     }
 
     #[test]
-    fn test_remove_identifier() {
-        let dots = Dots::default();
+    fn test_dedupe_skips_different_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Root");
+        fs::create_dir(&root).unwrap();
+        fs::write(root.join("My Movie.mkv"), "some content").unwrap();
+        fs::write(root.join("My.Movie.mkv"), "same content").unwrap();
+
+        let mut dots = Dots {
+            root: root.clone(),
+            config: Config {
+                dedupe: true,
+                dedupe_verify: true,
+                ..Config::default()
+            },
+            ..Dots::default()
+        };
+        let renames = vec![(root.join("My Movie.mkv"), root.join("My.Movie.mkv"))];
+        assert_eq!(dots.rename_paths(renames), 0);
+        assert!(dots.deduplicated.is_empty());
+        assert!(dots.failed.is_empty());
+        assert_eq!(fs::read_to_string(root.join("My Movie.mkv")).unwrap(), "some content");
+        assert_eq!(fs::read_to_string(root.join("My.Movie.mkv")).unwrap(), "same content");
+    }
+
+    #[test]
+    fn test_undo_reverts_most_recent_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Root");
+        fs::create_dir_all(root.join("sub dir")).unwrap();
+        fs::File::create(root.join("foo file.txt")).unwrap();
+        fs::File::create(root.join("sub dir").join("other foo.txt")).unwrap();
+
+        let mut dots = Dots {
+            root: root.clone(),
+            config: Config {
+                replace: vec![("foo".to_string(), "bar".to_string())],
+                recursive: true,
+                target: RenameTarget::Both,
+                ..Config::default()
+            },
+            ..Dots::default()
+        };
+        dots.run().unwrap();
+        assert!(root.join("Bar.File.txt").is_file());
+        assert!(root.join("Sub Dir").join("Other.Bar.txt").is_file());
+        assert_eq!(read_rename_log(&root).unwrap().runs.len(), 1);
+
+        undo_renames(Some(&root.to_string_lossy()), true).unwrap();
+        assert!(root.join("Bar.File.txt").is_file());
+
+        undo_renames(Some(&root.to_string_lossy()), false).unwrap();
+        assert!(root.join("foo file.txt").is_file());
+        assert!(root.join("sub dir").join("other foo.txt").is_file());
+        assert!(!root.join(RENAME_LOG).exists());
+        assert!(undo_renames(Some(&root.to_string_lossy()), false).is_err());
+    }
+
+    #[test]
+    fn test_undo_skips_changed_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Root");
+        fs::create_dir(&root).unwrap();
+        for name in ["first file.txt", "second file.txt", "third file.txt"] {
+            fs::File::create(root.join(name)).unwrap();
+        }
+        let mut dots = Dots {
+            root: root.clone(),
+            ..Dots::default()
+        };
+        dots.run().unwrap();
+
+        // New name removed and old name recreated after renaming
+        fs::remove_file(root.join("First.File.txt")).unwrap();
+        fs::File::create(root.join("second file.txt")).unwrap();
+
+        undo_renames(Some(&root.to_string_lossy()), false).unwrap();
+        assert!(!root.join("first file.txt").exists());
+        assert!(root.join("Second.File.txt").is_file());
+        assert!(root.join("third file.txt").is_file());
+
+        // Skipped renames stay in the log and can be reverted once the conflict is gone
+        let log = read_rename_log(&root).unwrap();
+        assert_eq!(log.runs.len(), 1);
+        assert_eq!(log.runs[0].len(), 2);
+        fs::remove_file(root.join("second file.txt")).unwrap();
+        undo_renames(Some(&root.to_string_lossy()), false).unwrap();
+        assert!(root.join("second file.txt").is_file());
+        assert_eq!(read_rename_log(&root).unwrap().runs[0].len(), 1);
+    }
+
+    #[test]
+    fn test_undo_keeps_failed_reverts() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Root");
+        fs::create_dir_all(root.join("Sub")).unwrap();
+        let log = RenameLog {
+            runs: vec![vec![RenameLogEntry {
+                old: root.join("missing dir").join("old name.txt"),
+                new: root.join("Sub").join("New.Name.txt"),
+                timestamp: String::new(),
+            }]],
+        };
+        fs::File::create(root.join("Sub").join("New.Name.txt")).unwrap();
+        write_rename_log_file(&root, &log).unwrap();
+
+        // The original parent directory is gone, so the revert fails
+        assert!(undo_renames(Some(&root.to_string_lossy()), false).is_err());
+        assert_eq!(read_rename_log(&root).unwrap().runs, log.runs);
+        assert!(root.join("Sub").join("New.Name.txt").is_file());
+    }
+
+    #[test]
+    fn test_rename_log_keeps_recent_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        for index in 0..MAX_LOGGED_RUNS + 2 {
+            let entry = RenameLogEntry {
+                old: dir.path().join(format!("old {index}")),
+                new: dir.path().join(format!("New.{index}")),
+                timestamp: String::new(),
+            };
+            append_rename_log(dir.path(), vec![entry]).unwrap();
+        }
+        let log = read_rename_log(dir.path()).unwrap();
+        assert_eq!(log.runs.len(), MAX_LOGGED_RUNS);
+        assert_eq!(log.runs[0][0].old, dir.path().join("old 2"));
+    }
+
+    #[test]
+    fn test_local_config_is_applied_per_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("Videos");
+        let second = dir.path().join("Other");
+        fs::create_dir_all(first.join("Season 1")).unwrap();
+        fs::create_dir(&second).unwrap();
+        fs::write(
+            first.join(LOCAL_CONFIG),
+            "remove = [\"WEBRip\"]\nsubstitute = [[\"Foo\", \"Bar\"]]\nregex = [[\"^(\\\\d+)\\\\.\", \"Episode.$1.\"]]\n",
+        )
+        .unwrap();
+        fs::File::create(first.join("Season 1").join("03 Foo Show WEBRip.mkv")).unwrap();
+        fs::File::create(second.join("Foo Show WEBRip.mkv")).unwrap();
+
+        let mut dots = Dots {
+            roots: vec![first.clone(), second.clone()],
+            config: Config {
+                replace: vec![("Foo".to_string(), "Baz".to_string())],
+                recursive: true,
+                ..Config::default()
+            },
+            ..Dots::default()
+        };
+        dots.run().unwrap();
+        assert!(first.join("Season 1").join("Episode.03.Baz.Show.mkv").is_file());
+        assert!(second.join("Baz.Show.WEBRip.mkv").is_file());
+        assert_eq!(dots.config.replace.len(), 1);
+        assert!(dots.config.regex_replace.is_empty());
+
+        fs::File::create(first.join("Foo Show WEBRip.mkv")).unwrap();
+        dots.roots = vec![first.clone()];
+        dots.config.no_local_rules = true;
+        dots.run().unwrap();
+        assert!(first.join("Baz.Show.WEBRip.mkv").is_file());
+    }
+
+    #[test]
+    fn test_local_config_prefix_and_validation() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join(LOCAL_CONFIG);
+        fs::write(&config, "prefix = \"Local\"\nsuffix = \"End\"\n").unwrap();
+        let mut global = Config {
+            prefix: Some("Cli".to_string()),
+            ..Config::default()
+        };
+        let saved = global.merge_local(LocalConfig::load(&config).unwrap());
+        assert_eq!(global.prefix.as_deref(), Some("Cli"));
+        assert_eq!(global.suffix.as_deref(), Some("End"));
+        global.restore_rules(saved);
+        assert!(global.suffix.is_none());
+
+        let nested = dir.path().join("nested").join("deeper");
+        fs::create_dir_all(&nested).unwrap();
+        assert_eq!(find_local_config(&nested), Some(config.clone()));
+
+        fs::write(&config, "regex = [[\"(unclosed\", \"\"]]\n").unwrap();
+        let error = format!("{:#}", LocalConfig::load(&config).unwrap_err());
+        assert!(error.contains("key 'regex' entry 1"), "{error}");
+        fs::write(&config, "unknown = 1\n").unwrap();
+        let error = format!("{:#}", LocalConfig::load(&config).unwrap_err());
+        assert!(error.contains("unknown"), "{error}");
+    }
+
+    #[test]
+    fn test_verify_rename() {
+        let dir = tempfile::tempdir().unwrap();
+        let old_path = dir.path().join("old name.txt");
+        let new_path = dir.path().join("New.Name.txt");
+        fs::File::create(&old_path).unwrap();
+
+        // Simulate a rename that reported success without changing anything
+        assert!(!verify_rename(&old_path, &new_path, false));
+
+        fs::rename(&old_path, &new_path).unwrap();
+        assert!(verify_rename(&old_path, &new_path, false));
+
+        let lower_path = dir.path().join("new.name.txt");
+        assert!(!verify_rename(&new_path, &lower_path, true));
+        fs::rename(&new_path, &lower_path).unwrap();
+        assert!(verify_rename(&new_path, &lower_path, true));
+    }
+
+    #[test]
+    fn test_is_moved_from() {
+        let dir = tempfile::tempdir().unwrap();
+        let old_dir = dir.path().join("old dir");
+        let new_dir = dir.path().join("New.Dir");
+        fs::create_dir(&old_dir).unwrap();
+        assert!(!is_moved_from(&old_dir, &new_dir, false));
+
+        // Both exist when the rename did not go through but the target was created
+        fs::create_dir(&new_dir).unwrap();
+        assert!(!is_moved_from(&old_dir, &new_dir, false));
+
+        fs::remove_dir(&old_dir).unwrap();
+        assert!(is_moved_from(&old_dir, &new_dir, false));
+
+        let lower_dir = dir.path().join("new.dir");
+        assert!(!is_moved_from(&new_dir, &lower_dir, true));
+        fs::rename(&new_dir, &lower_dir).unwrap();
+        assert!(is_moved_from(&new_dir, &lower_dir, true));
+    }
+
+    #[test]
+    fn test_verified_rename_and_sync() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Root");
+        fs::create_dir(&root).unwrap();
+        fs::File::create(root.join("some file.txt")).unwrap();
+
+        let mut dots = Dots {
+            root: root.clone(),
+            config: Config {
+                verify: true,
+                sync: true,
+                ..Config::default()
+            },
+            ..Dots::default()
+        };
+        let renames = vec![(root.join("some file.txt"), root.join("Some.File.txt"))];
+        assert_eq!(dots.rename_paths(renames), 1);
+        assert!(dots.unverified.is_empty());
+        assert!(dots.check_unverified().is_ok());
+
+        dots.unverified.push((root.join("a"), root.join("b")));
+        assert!(dots.check_unverified().is_err());
+    }
+
+    #[test]
+    fn test_select_paths_with_limit() {
+        let paths: Vec<(PathBuf, PathBuf)> = (0..10)
+            .map(|index| {
+                (
+                    PathBuf::from(format!("file {index}")),
+                    PathBuf::from(format!("File.{index}")),
+                )
+            })
+            .collect();
+        let mut dots = Dots {
+            config: Config {
+                limit: Some(4),
+                ..Config::default()
+            },
+            ..Dots::default()
+        };
+        assert_eq!(dots.select_paths(paths[..3].to_vec()), paths[..3].to_vec());
+        assert_eq!(dots.select_paths(paths[3..].to_vec()), paths[3..4].to_vec());
+        assert!(dots.select_paths(paths).is_empty());
+        assert_eq!(dots.num_selected, 4);
+        assert_eq!(dots.num_limited, 16);
+    }
+
+    #[test]
+    fn test_select_paths_with_seeded_sample() {
+        let paths: Vec<(PathBuf, PathBuf)> = (0..50)
+            .map(|index| {
+                (
+                    PathBuf::from(format!("file {index:02}")),
+                    PathBuf::from(format!("File.{index:02}")),
+                )
+            })
+            .collect();
+        let sample = |seed: u64| {
+            let mut dots = Dots {
+                config: Config {
+                    sample: Some(5),
+                    seed: Some(seed),
+                    ..Config::default()
+                },
+                ..Dots::default()
+            };
+            let selected = dots.select_paths(paths.clone());
+            assert_eq!(dots.num_limited, 45);
+            selected
+        };
+        let selected = sample(42);
+        assert_eq!(selected.len(), 5);
+        assert!(selected.is_sorted());
+        assert!(selected.iter().all(|pair| paths.contains(pair)));
+        assert_eq!(sample(42), selected);
+        assert_ne!(sample(7), selected);
+    }
+
+    #[test]
+    fn test_failed_renames_are_recorded() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Root");
+        fs::create_dir(&root).unwrap();
+        fs::File::create(root.join("first file.txt")).unwrap();
+
+        let mut dots = Dots {
+            root: root.clone(),
+            config: Config {
+                gentle: true,
+                retries: 2,
+                ..Config::default()
+            },
+            ..Dots::default()
+        };
+        let renames = vec![
+            (root.join("missing file.txt"), root.join("Missing.File.txt")),
+            (root.join("first file.txt"), root.join("First.File.txt")),
+        ];
+        assert_eq!(dots.rename_paths(renames), 1);
+        assert!(root.join("First.File.txt").exists());
+        assert_eq!(dots.failed.len(), 1);
+        assert_eq!(dots.failed[0].0, root.join("missing file.txt"));
+        assert!(dots.check_results().is_err());
+    }
+
+    #[test]
+    fn test_ask_rename_decision() {
+        let mut input = io::Cursor::new("y\nmaybe\nn\n\ne\n\ne\nsub/name\ne\nNew.Name\na\nq\n");
+        assert_eq!(ask_rename_decision(&mut input), RenameDecision::Apply);
+        assert_eq!(ask_rename_decision(&mut input), RenameDecision::Skip);
+        assert_eq!(ask_rename_decision(&mut input), RenameDecision::Skip);
         assert_eq!(
-            dots.format_name("This is a string test ^[640e54a564228]"),
-            "This.Is.a.String.Test"
+            ask_rename_decision(&mut input),
+            RenameDecision::Edit("New.Name".to_string())
         );
+        assert_eq!(ask_rename_decision(&mut input), RenameDecision::ApplyAll);
+        assert_eq!(ask_rename_decision(&mut input), RenameDecision::Quit);
+        assert_eq!(ask_rename_decision(&mut input), RenameDecision::Quit);
+    }
+
+    #[test]
+    fn test_interactive_rename() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Root");
+        fs::create_dir(&root).unwrap();
+        for name in [
+            "a file.txt",
+            "b file.txt",
+            "c file.txt",
+            "d file.txt",
+            "e file.txt",
+            "Existing.txt",
+        ] {
+            fs::File::create(root.join(name)).unwrap();
+        }
+        let renames = ["a", "b", "c", "d", "e"]
+            .iter()
+            .map(|name| {
+                (
+                    root.join(format!("{name} file.txt")),
+                    root.join(format!("{}.File.txt", name.to_uppercase())),
+                )
+            })
+            .collect();
+
+        let mut dots = Dots {
+            root: root.clone(),
+            config: Config {
+                interactive: true,
+                ..Config::default()
+            },
+            ..Dots::default()
+        };
+        let mut input = io::Cursor::new("n\ne\nEdited.txt\ne\nExisting.txt\ny\nq\n");
+        assert_eq!(dots.rename_paths_with_input(renames, &mut input, &RealFs), 2);
+        assert!(root.join("a file.txt").exists());
+        assert!(root.join("Edited.txt").exists());
+        assert!(root.join("c file.txt").exists());
+        assert!(root.join("D.File.txt").exists());
+        assert!(root.join("e file.txt").exists());
+        // The name edited to an existing file is skipped
+        assert_eq!(dots.interactive.num_skipped, 2);
+        assert_eq!(dots.interactive.num_edited, 1);
+        assert!(dots.interactive.quit);
+    }
+
+    #[test]
+    fn test_dryrun_records_renames() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Root");
+        fs::create_dir(&root).unwrap();
+        fs::File::create(root.join("first file.txt")).unwrap();
+        fs::File::create(root.join("second file.txt")).unwrap();
+        let renames = vec![
+            (root.join("first file.txt"), root.join("First.File.txt")),
+            (root.join("second file.txt"), root.join("Second.File.txt")),
+        ];
+
+        let mut dots = Dots {
+            root: root.clone(),
+            config: Config {
+                dryrun: true,
+                ..Config::default()
+            },
+            ..Dots::default()
+        };
+        let dry_run = DryRunFs::new();
         assert_eq!(
-            dots.format_name("This.Is.a.test.string.65f09e4248e03..."),
-            "This.Is.a.Test.String"
+            dots.rename_paths_with_input(renames.clone(), &mut io::empty(), &dry_run),
+            2
         );
-        assert_eq!(dots.format_name("test Ph5d9473a841fe9"), "Test");
-        assert_eq!(dots.format_name("Test-355989849"), "Test");
+        let expected: Vec<FsOp> = renames
+            .into_iter()
+            .map(|(from, to)| FsOp::Rename { from, to })
+            .collect();
+        assert_eq!(dry_run.into_operations(), expected);
+        assert!(root.join("first file.txt").exists());
+        assert!(root.join("second file.txt").exists());
+        assert!(dots.journal.is_empty());
     }
 }
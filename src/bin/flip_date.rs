@@ -4,6 +4,7 @@ use std::sync::LazyLock;
 
 use anyhow::{Context, Result};
 use clap::Parser;
+use cli_tools::date::{RE_DD_MM_YYYY, RE_YYYY_MM_DD};
 use colored::Colorize;
 use regex::{Captures, Regex};
 use walkdir::WalkDir;
@@ -11,16 +12,6 @@ use walkdir::WalkDir;
 static FILE_EXTENSIONS: [&str; 7] = ["m4a", "mp3", "txt", "rtf", "csv", "mp4", "mkv"];
 
 // Static variables that are initialised at runtime the first time they are accessed.
-static RE_DD_MM_YYYY: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(r"(?P<day>0*[1-9]\d?)\.(?P<month>0*[1-9]\d?)\.(?P<year>0*[1-9]\d{3})")
-        .expect("Failed to create regex pattern for dd.mm.yyyy")
-});
-
-static RE_YYYY_MM_DD: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(r"(?P<year>0*[1-9]\d{3})\.(?P<month>0*[1-9]\d?)\.(?P<day>0*[1-9]\d?)")
-        .expect("Failed to create regex pattern for yyyy.mm.dd")
-});
-
 static RE_CORRECT_DATE_FORMAT: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(r"0*[1-9]\d{3}\.0*[1-9]\d?\.(0*[1-9]\d?)").expect("Failed to create regex pattern for correct date")
 });
@@ -59,6 +50,18 @@ struct Args {
     /// Use recursive path handling
     #[arg(short, long)]
     recursive: bool,
+
+    /// Skip files and directories whose name contains the pattern
+    #[arg(short, long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// Skip files with the given extension
+    #[arg(long, value_name = "EXTENSION")]
+    not_ext: Vec<String>,
+
+    /// Include hidden files and directories
+    #[arg(long)]
+    include_hidden: bool,
 }
 
 #[derive(Debug)]
@@ -71,16 +74,34 @@ struct RenameItem {
 fn main() -> Result<()> {
     let args = Args::parse();
     let path = cli_tools::resolve_input_path(args.path.as_deref())?;
+    let skip_rules = skip_rules(&args);
     if args.dir {
-        date_flip_directories(path, args.recursive, args.print)
+        date_flip_directories(path, args.recursive, args.print, &skip_rules)
     } else {
-        date_flip_files(&path, args.recursive, args.print, args.year)
+        date_flip_files(&path, args.recursive, args.print, args.year, &skip_rules)
+    }
+}
+
+/// Walk rules for skipping excluded names and extensions, hidden paths, and version control directories.
+fn skip_rules(args: &Args) -> cli_tools::SkipRules {
+    cli_tools::SkipRules {
+        skip_hidden: !args.include_hidden,
+        skip_names: cli_tools::VCS_DIRECTORIES.iter().map(ToString::to_string).collect(),
+        skip_extensions: args.not_ext.clone(),
+        exclude: args.exclude.clone(),
+        ..cli_tools::SkipRules::default()
     }
 }
 
 /// Flip date to start with year for all matching files from the given path.
-fn date_flip_files(path: &PathBuf, recursive: bool, dryrun: bool, starts_with_year: bool) -> Result<()> {
-    let (files, root) = files_to_rename(path, recursive)?;
+fn date_flip_files(
+    path: &PathBuf,
+    recursive: bool,
+    dryrun: bool,
+    starts_with_year: bool,
+    skip_rules: &cli_tools::SkipRules,
+) -> Result<()> {
+    let (files, root) = files_to_rename(path, recursive, skip_rules)?;
     if files.is_empty() {
         anyhow::bail!("No files to process");
     }
@@ -103,7 +124,7 @@ fn date_flip_files(path: &PathBuf, recursive: bool, dryrun: bool, starts_with_ye
     }
 
     // Case-insensitive sort by filename
-    files_to_rename.sort_by(|a, b| a.filename.to_lowercase().cmp(&b.filename.to_lowercase()));
+    files_to_rename.sort_by_key(|a| a.filename.to_lowercase());
 
     let heading = if dryrun {
         "Dryrun:".cyan().bold()
@@ -123,8 +144,13 @@ fn date_flip_files(path: &PathBuf, recursive: bool, dryrun: bool, starts_with_ye
 }
 
 /// Flip date to start with year for all matching directories from given path.
-fn date_flip_directories(path: PathBuf, recursive: bool, dryrun: bool) -> Result<()> {
-    let directories = directories_to_rename(path, recursive)?;
+fn date_flip_directories(
+    path: PathBuf,
+    recursive: bool,
+    dryrun: bool,
+    skip_rules: &cli_tools::SkipRules,
+) -> Result<()> {
+    let directories = directories_to_rename(path, recursive, skip_rules)?;
     if directories.is_empty() {
         anyhow::bail!("No directories to rename")
     }
@@ -158,7 +184,11 @@ fn date_flip_directories(path: PathBuf, recursive: bool, dryrun: bool) -> Result
 }
 
 /// Get list of files to process
-fn files_to_rename(path: &PathBuf, recursive: bool) -> Result<(Vec<PathBuf>, PathBuf)> {
+fn files_to_rename(
+    path: &PathBuf,
+    recursive: bool,
+    skip_rules: &cli_tools::SkipRules,
+) -> Result<(Vec<PathBuf>, PathBuf)> {
     let (mut files, root) = if path.is_file() {
         (
             vec![path.clone()],
@@ -169,14 +199,15 @@ fn files_to_rename(path: &PathBuf, recursive: bool) -> Result<(Vec<PathBuf>, Pat
             .min_depth(1)
             .max_depth(if recursive { usize::MAX } else { 1 })
             .into_iter()
+            .filter_entry(|entry| !skip_rules.should_skip(entry))
             .filter_map(std::result::Result::ok)
             .map(walkdir::DirEntry::into_path)
             .filter(|path| {
                 path.is_file()
-                    && path.extension().map_or(false, |ext| {
+                    && path.extension().is_some_and(|ext| {
                         FILE_EXTENSIONS.contains(
                             &ext.to_str()
-                                .unwrap_or_else(|| panic!("Invalid file extension: {ext:#?}")),
+                                .unwrap_or_else(|| panic!("Invalid file extension: {}", ext.to_string_lossy())),
                         )
                     })
             })
@@ -189,12 +220,14 @@ fn files_to_rename(path: &PathBuf, recursive: bool) -> Result<(Vec<PathBuf>, Pat
 }
 
 /// Get list of directories to process
-fn directories_to_rename(path: PathBuf, recursive: bool) -> Result<Vec<RenameItem>> {
+fn directories_to_rename(path: PathBuf, recursive: bool, skip_rules: &cli_tools::SkipRules) -> Result<Vec<RenameItem>> {
     let mut directories_to_rename = Vec::new();
 
     let walker = WalkDir::new(path)
         .min_depth(1)
-        .max_depth(if recursive { 100 } else { 1 });
+        .max_depth(if recursive { 100 } else { 1 })
+        .into_iter()
+        .filter_entry(|entry| !skip_rules.should_skip(entry));
 
     for entry in walker {
         let entry = entry.context("Failed to read directory entry")?;
@@ -211,7 +244,7 @@ fn directories_to_rename(path: PathBuf, recursive: bool) -> Result<Vec<RenameIte
     }
 
     // Case-insensitive sort by filename
-    directories_to_rename.sort_by(|a, b| a.filename.to_lowercase().cmp(&b.filename.to_lowercase()));
+    directories_to_rename.sort_by_key(|a| a.filename.to_lowercase());
 
     Ok(directories_to_rename)
 }
@@ -538,3 +571,95 @@ mod directory_tests {
         assert_eq!(reorder_directory_date(dirname), None);
     }
 }
+
+#[cfg(test)]
+mod skip_tests {
+    use super::*;
+
+    /// Create a small project tree with dated files in version control and hidden directories.
+    fn create_repo_tree() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("Project");
+        for subdir in [
+            ".git/logs",
+            ".hidden",
+            "data",
+            "build 1.2.2023",
+            ".svn/1.2.2023",
+            "notes",
+        ] {
+            fs::create_dir_all(root.join(subdir)).unwrap();
+        }
+        for file in [
+            ".git/logs/1.2.2023.txt",
+            ".hidden/2.2.2023.txt",
+            "data/3.2.2023.csv",
+            "notes/4.2.2023.txt",
+            "notes/draft_5.2.2023.txt",
+            "6.2.2023.mp3",
+        ] {
+            fs::File::create(root.join(file)).unwrap();
+        }
+        (dir, root)
+    }
+
+    fn args(arguments: &[&str]) -> Args {
+        Args::parse_from(std::iter::once("flipdate").chain(arguments.iter().copied()))
+    }
+
+    fn file_names(root: &PathBuf, arguments: &[&str]) -> Vec<String> {
+        let (files, _) = files_to_rename(root, true, &skip_rules(&args(arguments))).unwrap();
+        files
+            .iter()
+            .map(|path| cli_tools::get_relative_path_or_filename(path, root))
+            .collect()
+    }
+
+    #[test]
+    fn test_skips_hidden_and_vcs_directories() {
+        let (_dir, root) = create_repo_tree();
+        assert_eq!(
+            file_names(&root, &[]),
+            vec![
+                "6.2.2023.mp3",
+                "data/3.2.2023.csv",
+                "notes/4.2.2023.txt",
+                "notes/draft_5.2.2023.txt"
+            ]
+        );
+        assert_eq!(
+            file_names(&root, &["--include-hidden"]),
+            vec![
+                ".hidden/2.2.2023.txt",
+                "6.2.2023.mp3",
+                "data/3.2.2023.csv",
+                "notes/4.2.2023.txt",
+                "notes/draft_5.2.2023.txt"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_exclude_patterns_and_extensions() {
+        let (_dir, root) = create_repo_tree();
+        assert_eq!(
+            file_names(&root, &["--exclude", "data", "-e", "draft", "--not-ext", "mp3"]),
+            vec!["notes/4.2.2023.txt"]
+        );
+    }
+
+    #[test]
+    fn test_directory_mode_uses_skip_rules() {
+        let (_dir, root) = create_repo_tree();
+        let names = |arguments: &[&str]| {
+            directories_to_rename(root.clone(), true, &skip_rules(&args(arguments)))
+                .unwrap()
+                .into_iter()
+                .map(|item| item.new_name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&[]), vec!["2023-02-01 build"]);
+        assert!(names(&["--exclude", "build"]).is_empty());
+        assert_eq!(names(&["--include-hidden"]), vec!["2023-02-01 build"]);
+    }
+}
@@ -1,20 +1,20 @@
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::ffi::OsStr;
-use std::fmt;
+use std::fmt::Write as _;
 use std::fs::File;
 use std::io::Write;
 use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
 use std::sync::LazyLock;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Context, Result};
 use chrono::{Datelike, Local, NaiveDate};
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use colored::Colorize;
 use regex::Regex;
-use rust_xlsxwriter::{Format, FormatAlign, FormatBorder, RowNum, Workbook};
-use serde::ser::{Serialize, SerializeStruct, Serializer};
+use rust_xlsxwriter::{Format, FormatAlign, FormatBorder, RowNum, Url, Workbook, Worksheet};
 
 use walkdir::WalkDir;
 
@@ -38,6 +38,9 @@ static RE_START_DATE: LazyLock<Regex> = LazyLock::new(|| {
         .expect("Failed to create regex pattern for start date")
 });
 
+static RE_FINVOICE_END: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"</Finvoice\s*>").expect("Failed to create regex pattern for Finvoice end tag"));
+
 static RE_SPECIFICATION_FREE_TEXT: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(r"^\s*<SpecificationFreeText>(.*?)</SpecificationFreeText>")
         .expect("Failed to create regex pattern for SpecificationFreeText")
@@ -87,6 +90,25 @@ static REPLACE_START: [&str; 11] = [
     "STOCKMANN",
 ];
 
+/// Show elapsed and estimated remaining time when parsing at least this many files.
+const PROGRESS_MIN_FILES: usize = 50;
+
+/// Excel sheet names that are used for the fixed sheets.
+const RESERVED_SHEET_NAMES: [&str; 4] = ["VISA", "DJ", "TOTALS", MISC_SHEET];
+
+/// Excel sheet for merchants that do not have their own sheet.
+const MISC_SHEET: &str = "Misc";
+
+/// Maximum length of an Excel sheet name.
+const MAX_SHEET_NAME_LENGTH: usize = 31;
+
+/// Name of the state file for the paths of parsed files.
+const PARSED_FILES_STATE: &str = "parsed_files";
+
+/// Excel number format for sums.
+/// Excel shows the decimal separator from the regional settings of the reader.
+const EXCEL_SUM_FORMAT: &str = "0.00";
+
 static FILTER_PREFIXES: [&str; 79] = [
     "1BAR",
     "45 SPECIAL",
@@ -192,11 +214,57 @@ struct Args {
     #[arg(short, long, default_value_t = 20)]
     number: usize,
 
+    /// How many top merchants get their own Excel sheet, others are grouped to a misc sheet
+    #[arg(short, long, default_value_t = 10)]
+    merchant_sheets: usize,
+
+    /// Fail if any file can not be parsed
+    #[arg(short, long)]
+    strict: bool,
+
+    /// Only parse files that were not parsed in earlier runs and write them to separate ".new" files
+    #[arg(long)]
+    only_new: bool,
+
+    /// Compare monthly totals to the previous month and the same month in the previous year
+    #[arg(short, long)]
+    compare: bool,
+
+    /// Language for the output headers and number format [default: original English CSV format]
+    #[arg(short, long, value_enum)]
+    locale: Option<Locale>,
+
     /// Verbose output
     #[arg(short, long)]
     verbose: bool,
 }
 
+/// Output language and number format.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Locale {
+    /// Finnish headers and comma as the decimal separator
+    Fi,
+    /// English headers and point as the decimal separator
+    En,
+    /// Output format from before the locale option, used when no locale is given:
+    /// English headers, comma-separated CSV, "yyyy.mm.dd" dates and point as the decimal separator
+    #[value(skip)]
+    #[default]
+    Legacy,
+}
+
+/// Total spending for one month compared to earlier periods.
+#[derive(Debug, Clone, PartialEq)]
+struct MonthComparison {
+    year: i32,
+    month: u32,
+    total: f64,
+    /// Total for the previous month if there is data for it
+    previous_month: Option<f64>,
+    /// Total for the same month in the previous year if there is data for it
+    previous_year: Option<f64>,
+}
+
 /// Represents one credit card purchase.
 #[derive(Debug, Clone, PartialEq)]
 struct VisaItem {
@@ -209,29 +277,82 @@ fn main() -> Result<()> {
     let args = Args::parse();
     let input_path = cli_tools::resolve_input_path(args.path.as_deref())?;
     let output_path = cli_tools::resolve_output_path(args.output.as_deref(), &input_path)?;
-    visa_parse(&input_path, &output_path, args.verbose, args.print, args.number)
+    visa_parse(&input_path, &output_path, &args)
 }
 
 /// Parse data from files and write formatted items to CSV and Excel.
-fn visa_parse(input: &PathBuf, output: &Path, verbose: bool, dryrun: bool, num_totals: usize) -> Result<()> {
-    let (root, files) = get_xml_file_list(input)?;
+fn visa_parse(input: &PathBuf, output: &Path, args: &Args) -> Result<()> {
+    let Args {
+        verbose,
+        print: dryrun,
+        strict,
+        only_new,
+        number: num_totals,
+        merchant_sheets,
+        compare,
+        locale,
+        ..
+    } = *args;
+    let locale = locale.unwrap_or_default();
+    let (root, mut files) = get_xml_file_list(input)?;
     if files.is_empty() {
         anyhow::bail!("No XML files to parse".red());
     }
 
-    let num_files = files.len();
-    let items = parse_files(&root, files, verbose)?;
+    let mut parsed_files: BTreeSet<PathBuf> = BTreeSet::new();
+    if only_new {
+        parsed_files = cli_tools::state::load_state("visa_parse", PARSED_FILES_STATE);
+        let num_all = files.len();
+        files = filter_new_files(files, &parsed_files);
+        if files.is_empty() {
+            println!("No new XML files to parse");
+            return Ok(());
+        }
+        println!("Skipping {} previously parsed files", num_all - files.len());
+    }
+
+    let (items, new_files) = parse_files(&root, files, verbose, strict, locale)?;
     let totals = calculate_totals_for_each_name(&items);
-    print_statistics(&items, &totals, num_files, verbose, num_totals);
+    print_statistics(&items, &totals, new_files.len(), verbose, num_totals, locale);
+    if compare {
+        print_month_comparison(&compare_months(&items), locale);
+    }
 
     if !dryrun {
-        write_to_csv(&items, output)?;
-        write_to_excel(&items, &totals, output)?;
+        // Keep the full report from earlier runs intact
+        let output = if only_new {
+            new_items_output_path(output)
+        } else {
+            output.to_path_buf()
+        };
+        write_to_csv(&items, &output, locale)?;
+        write_to_excel(&items, &totals, &output, merchant_sheets, locale)?;
+        if only_new {
+            parsed_files.extend(new_files);
+            cli_tools::state::save_state("visa_parse", PARSED_FILES_STATE, &parsed_files)?;
+        }
     }
 
     Ok(())
 }
 
+/// Output path for the items from new files only: "VISA.new.csv", or "name.new.csv" for an output file.
+fn new_items_output_path(output: &Path) -> PathBuf {
+    if output
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv") || ext.eq_ignore_ascii_case("xlsx"))
+    {
+        cli_tools::insert_suffix_before_extension(output, ".new")
+    } else {
+        output.join("VISA.new.csv")
+    }
+}
+
+/// Remove files that have already been parsed.
+fn filter_new_files(files: Vec<PathBuf>, parsed_files: &BTreeSet<PathBuf>) -> Vec<PathBuf> {
+    files.into_iter().filter(|file| !parsed_files.contains(file)).collect()
+}
+
 /// Return file root and list of files from the input path that can be either a directory or single file.
 fn get_xml_file_list(input: &PathBuf) -> Result<(PathBuf, Vec<PathBuf>)> {
     if input.is_file() {
@@ -270,69 +391,152 @@ fn get_xml_files<P: AsRef<Path>>(root: P) -> Vec<PathBuf> {
 }
 
 /// Parse raw XML files.
-fn parse_files(root: &Path, files: Vec<PathBuf>, verbose: bool) -> Result<Vec<VisaItem>> {
+///
+/// Files that can not be read or parsed are skipped and listed at the end.
+/// Returns the parsed items and the successfully parsed files.
+/// Fails if none of the files could be parsed, or if any file failed in strict mode.
+fn parse_files(
+    root: &Path,
+    files: Vec<PathBuf>,
+    verbose: bool,
+    strict: bool,
+    locale: Locale,
+) -> Result<(Vec<VisaItem>, Vec<PathBuf>)> {
     let mut result: Vec<VisaItem> = Vec::new();
+    let mut parsed_files: Vec<PathBuf> = Vec::new();
+    let mut failures: Vec<(String, anyhow::Error)> = Vec::new();
     let num_files = files.len();
     let digits = if num_files < 10 {
         1
     } else {
         ((num_files as f64).log10() as usize) + 1
     };
+    let show_progress = num_files >= PROGRESS_MIN_FILES;
+    let output = cli_tools::output::OutputCoordinator::global();
+    // Draw progress on its own line when possible, otherwise append it to each file line
+    let progress = (show_progress && output.is_interactive()).then(|| output.start_progress());
+    let start = Instant::now();
 
     for (number, file) in files.into_iter().enumerate() {
-        print!(
-            "{}",
-            format!(
-                "{:>0width$}: {}",
-                number + 1,
-                cli_tools::get_relative_path_or_filename(&file, root),
-                width = digits
-            )
+        let relative_path = cli_tools::get_relative_path_or_filename(&file, root);
+        let mut line = format!("{:>0width$}: {}", number + 1, relative_path, width = digits)
             .bold()
-        );
-        let (raw_lines, year) = read_xml_file(&file);
-        let items = extract_items(&raw_lines, year)?;
-        if items.is_empty() {
-            println!(" ({})", "0".yellow());
-        } else {
-            println!(" ({})", format!("{}", items.len()).cyan());
-            if verbose {
-                for item in &items {
-                    println!("  {item}");
+            .to_string();
+        match parse_file(&file) {
+            Ok(items) => {
+                if items.is_empty() {
+                    let _ = write!(line, " ({})", "0".yellow());
+                } else {
+                    let _ = write!(line, " ({})", format!("{}", items.len()).cyan());
+                }
+                if show_progress && progress.is_none() {
+                    let _ = write!(
+                        line,
+                        " {}",
+                        format_progress(start.elapsed(), number + 1, num_files).dimmed()
+                    );
                 }
+                cli_tools::output::print_line(&line);
+                if verbose {
+                    for item in &items {
+                        cli_tools::output::print_line(&format!("  {}", item.display(locale)));
+                    }
+                }
+                result.extend(items);
+                parsed_files.push(file);
+            }
+            Err(error) => {
+                let _ = write!(line, " ({})", "failed".red());
+                cli_tools::output::print_line(&line);
+                failures.push((relative_path, error));
             }
-            result.extend(items);
+        }
+        if let Some(progress) = &progress {
+            progress.update(
+                &format_progress(start.elapsed(), number + 1, num_files)
+                    .dimmed()
+                    .to_string(),
+            );
         }
     }
+    drop(progress);
 
     result.sort();
+    let num_parsed = num_files - failures.len();
     println!(
         "Found {} items from {}",
         result.len(),
-        if num_files > 1 {
-            format!("{num_files} files")
-        } else {
+        if num_parsed == 1 {
             "1 file".to_string()
+        } else {
+            format!("{num_parsed} files")
         }
     );
 
-    Ok(result)
+    if !failures.is_empty() {
+        println!(
+            "\n{}",
+            format!("Failed to parse {} of {num_files} files:", failures.len())
+                .red()
+                .bold()
+        );
+        for (name, error) in &failures {
+            println!("{}", format!("  {name}: {error:#}").red());
+        }
+        println!();
+        if num_parsed == 0 {
+            anyhow::bail!("Failed to parse any of the XML files");
+        }
+        if strict {
+            anyhow::bail!("Failed to parse {} XML files", failures.len());
+        }
+    }
+
+    Ok((result, parsed_files))
+}
+
+/// Read and convert all items from a single XML file.
+fn parse_file(file: &Path) -> Result<Vec<VisaItem>> {
+    let (raw_lines, year) = read_xml_file(file)?;
+    extract_items(&raw_lines, year)
+}
+
+/// Format elapsed time and the estimated remaining time based on the average time per file.
+fn format_progress(elapsed: Duration, done: usize, total: usize) -> String {
+    let average = elapsed.as_secs_f64() / done.max(1) as f64;
+    let remaining = Duration::from_secs_f64(average * total.saturating_sub(done) as f64);
+    format!(
+        "[{} elapsed, ~{} remaining]",
+        format_duration(elapsed),
+        format_duration(remaining)
+    )
+}
+
+/// Format duration as seconds, or minutes and seconds for longer durations.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64();
+    if seconds < 60.0 {
+        format!("{seconds:.1}s")
+    } else {
+        let whole_seconds = duration.as_secs();
+        format!("{}m {:02}s", whole_seconds / 60, whole_seconds % 60)
+    }
 }
 
 /// Read transaction lines from an XML file.
-fn read_xml_file(file: &Path) -> (Vec<String>, i32) {
-    let mut lines: Vec<String> = Vec::new();
+///
+/// Fails if the file can not be read or is missing the closing Finvoice tag.
+/// Invalid UTF-8 is replaced instead of failing so that Latin-1 encoded statements can still be parsed.
+fn read_xml_file(file: &Path) -> Result<(Vec<(usize, String)>, i32)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
     let mut year = Local::now().year();
-    let xml_file = match File::open(file) {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!("{}", format!("Failed to open file: {}\n{}", file.display(), e).red());
-            return (lines, year);
-        }
-    };
+    let mut complete = false;
+    let xml_file = File::open(file).context("Failed to open file")?;
 
     let reader = BufReader::new(xml_file);
-    for line in reader.lines().map_while(Result::ok) {
+    for (index, line) in reader.split(b'\n').enumerate() {
+        let line = line.with_context(|| format!("Failed to read line {}", index + 1))?;
+        let line = String::from_utf8_lossy(&line);
         if let Some(caps) = RE_START_DATE.captures(&line) {
             if let Some(matched) = caps.get(1) {
                 match matched.as_str().parse::<i32>() {
@@ -349,26 +553,32 @@ fn read_xml_file(file: &Path) -> (Vec<String>, i32) {
             if let Some(matched) = caps.get(1) {
                 let text = matched.as_str();
                 if RE_ITEM_DATE.is_match(text) {
-                    lines.push(text.to_string());
+                    lines.push((index + 1, text.to_string()));
                 }
             }
         }
+        if RE_FINVOICE_END.is_match(&line) {
+            complete = true;
+        }
     }
-    (lines, year)
+    if !complete {
+        anyhow::bail!("Missing closing Finvoice tag, file is truncated or not a Finvoice XML");
+    }
+    Ok((lines, year))
 }
 
 /// Convert text lines to visa items.
-fn extract_items(rows: &[String], year: i32) -> Result<Vec<VisaItem>> {
+fn extract_items(rows: &[(usize, String)], year: i32) -> Result<Vec<VisaItem>> {
     let mut formatted_data: Vec<(i32, i32, String, f64)> = Vec::new();
-    for line in rows {
+    for (line_number, line) in rows {
         let (date, name, sum) = split_item_text(line);
         let (day, month) = date
             .split_once('.')
             .with_context(|| format!("Failed to separate day and month: {date}"))?;
         let month: i32 = month.replace('.', "").parse()?;
         let day: i32 = day.parse()?;
-        let name = format_name(&name);
-        let sum = format_sum(&sum).with_context(|| format!("Failed format sum: {sum}"))?;
+        let name = clean_name(&name);
+        let sum = format_sum(&sum).with_context(|| format!("Failed to parse sum on line {line_number}"))?;
         formatted_data.push((day, month, name, sum));
     }
 
@@ -415,6 +625,77 @@ fn calculate_totals_for_each_name(items: &[VisaItem]) -> Vec<(String, f64)> {
     totals_vec
 }
 
+/// Calculate the total sum for each month and compare it to the previous month
+/// and the same month in the previous year. Returns months in chronological order.
+fn compare_months(items: &[VisaItem]) -> Vec<MonthComparison> {
+    let mut monthly_totals: BTreeMap<(i32, u32), f64> = BTreeMap::new();
+    for item in items {
+        *monthly_totals
+            .entry((item.date.year(), item.date.month()))
+            .or_insert(0.0) += item.sum;
+    }
+    monthly_totals
+        .iter()
+        .map(|(&(year, month), &total)| {
+            let previous = if month == 1 { (year - 1, 12) } else { (year, month - 1) };
+            MonthComparison {
+                year,
+                month,
+                total,
+                previous_month: monthly_totals.get(&previous).copied(),
+                previous_year: monthly_totals.get(&(year - 1, month)).copied(),
+            }
+        })
+        .collect()
+}
+
+/// Print a table of monthly totals with changes to earlier periods.
+/// Increased spending is shown in red and decreased spending in green.
+fn print_month_comparison(comparisons: &[MonthComparison], locale: Locale) {
+    if comparisons.len() < 2 {
+        println!("Only one month of data, nothing to compare\n");
+        return;
+    }
+    println!("{}", "Monthly comparison:".bold());
+    println!(
+        "{:<7}  {:>11}  {:>24}  {:>24}",
+        "Month", "Total", "vs previous month", "vs previous year"
+    );
+    for comparison in comparisons {
+        let total = format!("{}€", locale.format_sum(comparison.total));
+        println!(
+            "{}-{:02}  {total:>11}  {}  {}",
+            comparison.year,
+            comparison.month,
+            format_change(comparison.total, comparison.previous_month, locale),
+            format_change(comparison.total, comparison.previous_year, locale),
+        );
+    }
+    println!();
+}
+
+/// Format the absolute and relative change from the previous value, padded to the table column width.
+fn format_change(total: f64, previous: Option<f64>, locale: Locale) -> String {
+    let Some(previous) = previous else {
+        return format!("{:>24}", "-");
+    };
+    let delta = total - previous;
+    let sign = if delta > 0.0 { "+" } else { "" };
+    let mut text = format!("{sign}{}€", locale.format_sum(delta));
+    if previous.abs() > f64::EPSILON {
+        let percent = delta / previous.abs() * 100.0;
+        let _ = write!(text, " ({sign}{}%)", locale.format_percent(percent));
+    }
+    let text = format!("{text:>24}");
+    if delta > 0.0 {
+        text.red().to_string()
+    } else if delta < 0.0 {
+        text.green().to_string()
+    } else {
+        text
+    }
+}
+
 /// Remove extra whitespaces and separators.
 fn clean_whitespaces(text: &str) -> String {
     RE_WHITESPACE
@@ -422,9 +703,13 @@ fn clean_whitespaces(text: &str) -> String {
         .to_string()
 }
 
-/// Format item names to consistent style.
-fn format_name(text: &str) -> String {
-    let mut name = text
+/// Format a raw item name to consistent style.
+///
+/// The steps are applied in order: whitespace cleanup, prefix and character removal,
+/// HTML entities, brackets, upper case, and then the replace tables
+/// `REPLACE_CONTAINS`, `REPLACE_PAIRS`, `REPLACE_START_PAIRS` and `REPLACE_START`.
+fn clean_name(raw: &str) -> String {
+    let mut name = clean_whitespaces(raw)
         .replace("Osto ", "")
         .replace("TC*", "")
         .replace(['*', '/', '_'], " ");
@@ -461,24 +746,81 @@ fn format_name(text: &str) -> String {
 }
 
 /// Convert Finnish currency value strings using a comma as the decimal separator to float.
+///
+/// Thousands can be separated with spaces, non-breaking spaces, or dots,
+/// and every group after the first must have three digits.
+/// A dot without a decimal comma is a decimal point,
+/// except when followed by exactly three digits which is ambiguous and rejected.
 fn format_sum(value: &str) -> Result<f64> {
-    value
-        .trim()
-        .replace(',', ".")
-        .replace(' ', "")
+    let trimmed = value.trim();
+    let (sign, unsigned) = trimmed
+        .strip_prefix('-')
+        .map_or(("", trimmed), |rest| ("-", rest.trim_start()));
+    let (integer, decimals) = match unsigned.rsplit_once(',') {
+        Some((integer, decimals)) => (integer, decimals),
+        None => match unsigned.split_once('.') {
+            Some((integer, decimals)) if !decimals.contains('.') => {
+                if decimals.len() == 3 {
+                    anyhow::bail!("Ambiguous sum, the dot can be a decimal or thousands separator: {value}");
+                }
+                (integer, decimals)
+            }
+            _ => (unsigned, ""),
+        },
+    };
+    if !decimals.chars().all(|c| c.is_ascii_digit()) {
+        anyhow::bail!("Invalid decimals in sum: {value}");
+    }
+    let integer = remove_thousands_separators(integer).with_context(|| format!("Invalid sum: {value}"))?;
+    let number = if decimals.is_empty() {
+        format!("{sign}{integer}")
+    } else {
+        format!("{sign}{integer}.{decimals}")
+    };
+    number
         .parse::<f64>()
-        .context(format!("Failed to parse sum as float: {value}"))
+        .with_context(|| format!("Failed to parse sum as float: {value}"))
+}
+
+/// Remove thousands separators from the integer part of a number.
+/// All separators must be of the same kind and separate groups of three digits.
+fn remove_thousands_separators(integer: &str) -> Result<String> {
+    let is_space = |c: char| c == ' ' || c == '\u{a0}' || c == '\u{202f}';
+    let groups: Vec<&str> = if integer.contains('.') {
+        if integer.contains(is_space) {
+            anyhow::bail!("Mixed thousands separators");
+        }
+        integer.split('.').collect()
+    } else {
+        integer.split(is_space).collect()
+    };
+    let (first, rest) = groups.split_first().context("Missing digits")?;
+    let valid_first = !first.is_empty() && (rest.is_empty() || first.len() <= 3);
+    if !valid_first || rest.iter().any(|group| group.len() != 3) {
+        anyhow::bail!("Invalid thousands grouping");
+    }
+    if !groups.iter().all(|group| group.chars().all(|c| c.is_ascii_digit())) {
+        anyhow::bail!("Sum contains non-digit characters");
+    }
+    Ok(groups.concat())
 }
 
 /// Print item totals and some statistics.
-fn print_statistics(items: &[VisaItem], totals: &[(String, f64)], num_files: usize, verbose: bool, num_totals: usize) {
+fn print_statistics(
+    items: &[VisaItem],
+    totals: &[(String, f64)],
+    num_files: usize,
+    verbose: bool,
+    num_totals: usize,
+    locale: Locale,
+) {
     let total_sum: f64 = items.iter().map(|item| item.sum).sum();
     let count = items.len() as f64;
     let average = if count > 0.0 { total_sum / count } else { 0.0 };
 
     println!("Average items per file: {:.1}", items.len() / num_files);
-    println!("Total sum: {total_sum:.2}€");
-    println!("Average sum: {average:.2}€");
+    println!("Total sum: {}€", locale.format_sum(total_sum));
+    println!("Average sum: {}€", locale.format_sum(average));
     println!("Unique names: {}", totals.len());
 
     if verbose {
@@ -491,7 +833,12 @@ fn print_statistics(items: &[VisaItem], totals: &[(String, f64)], num_files: usi
 
         println!("\n{}", format!("Top {num_totals} totals:").bold());
         for (name, sum) in &totals[..num_totals] {
-            println!("{:width$}    {:>7.2}€", format!("{name}"), sum, width = max_name_length);
+            println!(
+                "{:width$}    {:>7}€",
+                format!("{name}"),
+                locale.format_sum(*sum),
+                width = max_name_length
+            );
         }
     }
     println!();
@@ -522,11 +869,11 @@ fn split_from_last_whitespaces(s: &str) -> (&str, &str) {
 }
 
 /// Save parsed data to a CSV file
-fn write_to_csv(items: &[VisaItem], output_path: &Path) -> Result<()> {
+fn write_to_csv(items: &[VisaItem], output_path: &Path, locale: Locale) -> Result<()> {
     let output_file = if output_path
         .extension()
         .and_then(|ext| ext.to_str())
-        .map_or(false, |ext| ext.eq_ignore_ascii_case("csv"))
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"))
     {
         output_path.to_path_buf()
     } else {
@@ -542,21 +889,53 @@ fn write_to_csv(items: &[VisaItem], output_path: &Path) -> Result<()> {
         }
     }
     let mut file = File::create(output_file)?;
-    writeln!(file, "Date,Sum,Name")?;
+    write!(file, "{}", format_csv(items, locale))?;
+    Ok(())
+}
+
+/// Format items as CSV with the locale headers, delimiter, and decimal separator.
+fn format_csv(items: &[VisaItem], locale: Locale) -> String {
+    let delimiter = locale.csv_delimiter();
+    let [date, name, sum] = locale.headers();
+    let mut csv = format!("{date}{delimiter}{sum}{delimiter}{name}\n");
     for item in items {
-        writeln!(file, "{},{:.2},{}", item.finnish_date(), item.sum, item.name)?;
+        // Writing to a String can not fail
+        let _ = writeln!(
+            csv,
+            "{}{delimiter}{}{delimiter}{}",
+            locale.format_date(item.date),
+            locale.format_sum(item.sum),
+            quote_csv_field(&item.name, delimiter)
+        );
+    }
+    csv
+}
+
+/// Quote CSV field if it contains the delimiter or quotes.
+fn quote_csv_field(field: &str, delimiter: char) -> String {
+    if field.contains(delimiter) || field.contains('"') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
     }
-    Ok(())
 }
 
 /// Save parsed data to an Excel file.
-fn write_to_excel(items: &[VisaItem], totals: &[(String, f64)], output_path: &Path) -> Result<()> {
+///
+/// The totals sheet links each merchant to a detail sheet with its transactions.
+/// The top merchants get their own sheets, and the rest share a misc sheet.
+fn write_to_excel(
+    items: &[VisaItem],
+    totals: &[(String, f64)],
+    output_path: &Path,
+    num_merchant_sheets: usize,
+    locale: Locale,
+) -> Result<()> {
     let output_file = if output_path
         .extension()
         .and_then(|ext| ext.to_str())
-        .map_or(false, |ext| {
-            ext.eq_ignore_ascii_case("csv") || ext.eq_ignore_ascii_case("xlsx")
-        }) {
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv") || ext.eq_ignore_ascii_case("xlsx"))
+    {
         output_path.with_extension("xlsx")
     } else {
         output_path.join("VISA.xlsx")
@@ -566,43 +945,79 @@ fn write_to_excel(items: &[VisaItem], totals: &[(String, f64)], output_path: &Pa
         format!("Writing data to Excel: {}", output_file.display()).green()
     );
     let mut workbook = Workbook::new();
-    let sheet = workbook.add_worksheet().set_name("VISA")?;
     let header_format = Format::new()
         .set_bold()
         .set_border(FormatBorder::Thin)
         .set_background_color("C6E0B4");
+    let sum_format = Format::new()
+        .set_align(FormatAlign::Right)
+        .set_num_format(EXCEL_SUM_FORMAT);
+    let subtotal_format = sum_format.clone().set_bold();
 
-    sheet.serialize_headers_with_format::<VisaItem>(0, 0, &items[0], &header_format)?;
-    sheet.serialize(&items)?;
-    sheet.autofit();
+    let all_items: Vec<&VisaItem> = items.iter().collect();
+    let sheet = workbook.add_worksheet().set_name("VISA")?;
+    write_item_sheet(sheet, &all_items, locale, &header_format, &sum_format)?;
 
+    // Filter out common non-DJ items
+    let dj_items: Vec<&VisaItem> = items
+        .iter()
+        .filter(|item| !FILTER_PREFIXES.iter().any(|&prefix| item.name.starts_with(prefix)))
+        .collect();
     let dj_sheet = workbook.add_worksheet().set_name("DJ")?;
-    let sum_format = Format::new().set_align(FormatAlign::Right).set_num_format("0,00");
-    dj_sheet.serialize_headers_with_format::<VisaItem>(0, 0, &items[0], &header_format)?;
-    let mut row: usize = 1;
-    for item in items {
-        // Filter out common non-DJ items
-        if FILTER_PREFIXES.iter().any(|&prefix| item.name.starts_with(prefix)) {
-            continue;
-        }
-        dj_sheet.write_string(row as RowNum, 0, item.finnish_date())?;
-        dj_sheet.write_string(row as RowNum, 1, item.name.clone())?;
-        dj_sheet.write_string_with_format(row as RowNum, 2, item.finnish_sum(), &sum_format)?;
-        row += 1;
-    }
-    dj_sheet.autofit();
+    write_item_sheet(dj_sheet, &dj_items, locale, &header_format, &sum_format)?;
 
+    let merchant_sheets = merchant_sheet_names(totals, num_merchant_sheets);
+    let has_misc_sheet = num_merchant_sheets > 0 && totals.len() > merchant_sheets.len();
     let totals_sheet = workbook.add_worksheet().set_name("TOTALS")?;
-    totals_sheet.write_string_with_format(0, 0, "Name", &header_format)?;
-    totals_sheet.write_string_with_format(0, 1, "Total sum", &header_format)?;
-    row = 1;
-    for (name, sum) in totals {
-        totals_sheet.write_string(row as RowNum, 0, name)?;
-        totals_sheet.write_string_with_format(row as RowNum, 1, format!("{sum:.2}").replace('.', ","), &sum_format)?;
-        row += 1;
+    let [_, name_header, _] = locale.headers();
+    totals_sheet.write_string_with_format(0, 0, name_header, &header_format)?;
+    totals_sheet.write_string_with_format(0, 1, locale.total_sum_header(), &header_format)?;
+    for (index, (name, sum)) in totals.iter().enumerate() {
+        let row = index as RowNum + 1;
+        let sheet_name = merchant_sheets
+            .get(name)
+            .map(String::as_str)
+            .or_else(|| has_misc_sheet.then_some(MISC_SHEET));
+        if let Some(sheet_name) = sheet_name {
+            let link = format!("internal:'{}'!A1", sheet_name.replace('\'', "''"));
+            totals_sheet.write_url(row, 0, Url::new(link).set_text(name))?;
+        } else {
+            totals_sheet.write_string(row, 0, name)?;
+        }
+        totals_sheet.write_number_with_format(row, 1, *sum, &sum_format)?;
     }
     totals_sheet.autofit();
 
+    let mut items_by_name: HashMap<&str, Vec<&VisaItem>> = HashMap::new();
+    for item in items {
+        items_by_name.entry(item.name.as_str()).or_default().push(item);
+    }
+    for (name, _) in totals.iter().filter(|(name, _)| merchant_sheets.contains_key(name)) {
+        let sheet = workbook.add_worksheet().set_name(&merchant_sheets[name])?;
+        let merchant_items = items_by_name.remove(name.as_str()).unwrap_or_default();
+        write_detail_sheet(
+            sheet,
+            &merchant_items,
+            locale,
+            &header_format,
+            &sum_format,
+            &subtotal_format,
+        )?;
+    }
+    if has_misc_sheet {
+        let mut misc_items: Vec<&VisaItem> = items_by_name.into_values().flatten().collect();
+        misc_items.sort();
+        let sheet = workbook.add_worksheet().set_name(MISC_SHEET)?;
+        write_detail_sheet(
+            sheet,
+            &misc_items,
+            locale,
+            &header_format,
+            &sum_format,
+            &subtotal_format,
+        )?;
+    }
+
     if output_file.exists() {
         if let Err(e) = std::fs::remove_file(&output_file) {
             eprintln!("{}", format!("Failed to remove existing xlsx file: {e}").red());
@@ -612,25 +1027,156 @@ fn write_to_excel(items: &[VisaItem], totals: &[(String, f64)], output_path: &Pa
     Ok(())
 }
 
-impl VisaItem {
-    /// Float value formatted with a comma as the decimal separator.
-    pub fn finnish_sum(&self) -> String {
-        format!("{:.2}", self.sum).replace('.', ",")
+/// Write header and item rows to the sheet.
+/// Returns the next empty row.
+fn write_item_sheet(
+    sheet: &mut Worksheet,
+    items: &[&VisaItem],
+    locale: Locale,
+    header_format: &Format,
+    sum_format: &Format,
+) -> Result<RowNum> {
+    for (column, header) in locale.headers().into_iter().enumerate() {
+        sheet.write_string_with_format(0, column as u16, header, header_format)?;
     }
+    let mut row: RowNum = 1;
+    for item in items {
+        sheet.write_string(row, 0, locale.format_date(item.date))?;
+        sheet.write_string(row, 1, &item.name)?;
+        sheet.write_number_with_format(row, 2, item.sum, sum_format)?;
+        row += 1;
+    }
+    sheet.autofit();
+    Ok(row)
+}
+
+/// Write merchant items sorted by date with a subtotal row.
+fn write_detail_sheet(
+    sheet: &mut Worksheet,
+    items: &[&VisaItem],
+    locale: Locale,
+    header_format: &Format,
+    sum_format: &Format,
+    subtotal_format: &Format,
+) -> Result<()> {
+    let row = write_item_sheet(sheet, items, locale, header_format, sum_format)?;
+    let subtotal: f64 = items.iter().map(|item| item.sum).sum();
+    sheet.write_string_with_format(row, 1, locale.total_sum_header(), &Format::new().set_bold())?;
+    sheet.write_number_with_format(row, 2, subtotal, subtotal_format)?;
+    Ok(())
+}
+
+/// Get unique, valid Excel sheet names for the given number of top merchants.
+fn merchant_sheet_names(totals: &[(String, f64)], count: usize) -> HashMap<String, String> {
+    let mut used: HashSet<String> = RESERVED_SHEET_NAMES.iter().map(|name| name.to_lowercase()).collect();
+    totals
+        .iter()
+        .take(count)
+        .map(|(name, _)| (name.clone(), sanitize_sheet_name(name, &mut used)))
+        .collect()
+}
+
+/// Convert the name to a valid Excel sheet name that is not in the used names.
+///
+/// Excel sheet names can be at most 31 characters, can not contain any of `[]:*?/\`,
+/// can not start or end with an apostrophe, and must be unique ignoring case.
+fn sanitize_sheet_name(name: &str, used: &mut HashSet<String>) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| {
+            if matches!(c, '[' | ']' | ':' | '*' | '?' | '/' | '\\') {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    let cleaned = cleaned.trim().trim_matches('\'').trim();
+    let base: String = if cleaned.is_empty() {
+        "Sheet".to_string()
+    } else {
+        cleaned
+            .chars()
+            .take(MAX_SHEET_NAME_LENGTH)
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+    };
 
-    /// Date in format "yyyy.mm.dd"
-    pub fn finnish_date(&self) -> String {
-        self.date.format("%Y.%m.%d").to_string()
+    let mut sheet_name = base.clone();
+    let mut number = 2;
+    while used.contains(&sheet_name.to_lowercase()) {
+        let suffix = format!(" ({number})");
+        let prefix: String = base
+            .chars()
+            .take(MAX_SHEET_NAME_LENGTH - suffix.chars().count())
+            .collect();
+        sheet_name = format!("{}{suffix}", prefix.trim_end());
+        number += 1;
     }
+    used.insert(sheet_name.to_lowercase());
+    sheet_name
 }
 
-impl fmt::Display for VisaItem {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
+impl Locale {
+    /// Column headers for date, name, and sum.
+    const fn headers(self) -> [&'static str; 3] {
+        match self {
+            Self::Fi => ["Päivämäärä", "Nimi", "Summa"],
+            Self::En | Self::Legacy => ["Date", "Name", "Sum"],
+        }
+    }
+
+    const fn total_sum_header(self) -> &'static str {
+        match self {
+            Self::Fi => "Yhteensä",
+            Self::En | Self::Legacy => "Total sum",
+        }
+    }
+
+    const fn decimal_separator(self) -> char {
+        match self {
+            Self::Fi => ',',
+            Self::En | Self::Legacy => '.',
+        }
+    }
+
+    /// CSV delimiter that does not clash with the decimal separator.
+    const fn csv_delimiter(self) -> char {
+        match self {
+            Self::Fi => ';',
+            Self::En | Self::Legacy => ',',
+        }
+    }
+
+    /// Float value formatted with two decimals and the locale decimal separator.
+    fn format_sum(self, sum: f64) -> String {
+        format!("{sum:.2}").replace('.', &self.decimal_separator().to_string())
+    }
+
+    /// Percentage with one decimal and the locale decimal separator.
+    fn format_percent(self, percent: f64) -> String {
+        format!("{percent:.1}").replace('.', &self.decimal_separator().to_string())
+    }
+
+    /// Date in format "yyyy.mm.dd" for Finnish and "yyyy-mm-dd" for English.
+    fn format_date(self, date: NaiveDate) -> String {
+        match self {
+            Self::Fi | Self::Legacy => date.format("%Y.%m.%d").to_string(),
+            Self::En => date.format("%Y-%m-%d").to_string(),
+        }
+    }
+}
+
+impl VisaItem {
+    /// Item as a console line with the locale date and number format.
+    fn display(&self, locale: Locale) -> String {
+        // Item lines used the Finnish format before the locale option
+        let locale = if locale == Locale::Legacy { Locale::Fi } else { locale };
+        format!(
             "{}   {:>7}€   {}",
-            self.finnish_date(),
-            self.finnish_sum(),
+            locale.format_date(self.date),
+            locale.format_sum(self.sum),
             self.name
         )
     }
@@ -654,21 +1200,6 @@ impl Ord for VisaItem {
     }
 }
 
-impl Serialize for VisaItem {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let mut state = serializer.serialize_struct("VisaItem", 3)?;
-
-        state.serialize_field("Date", &self.finnish_date())?;
-        state.serialize_field("Name", &self.name)?;
-        state.serialize_field("Sum", &self.finnish_sum())?;
-
-        state.end()
-    }
-}
-
 #[cfg(test)]
 mod test_format_sum {
     use super::*;
@@ -724,6 +1255,169 @@ mod test_format_sum {
     fn test_number_with_thousand_space() {
         assert_f64_eq(format_sum("1 488,90").unwrap(), 1488.90);
     }
+
+    #[test]
+    fn test_thousands_separators() {
+        assert_f64_eq(format_sum("1234,56").unwrap(), 1234.56);
+        assert_f64_eq(format_sum("1 234,56").unwrap(), 1234.56);
+        assert_f64_eq(format_sum("1\u{a0}234,56").unwrap(), 1234.56);
+        assert_f64_eq(format_sum("1\u{202f}234,56").unwrap(), 1234.56);
+        assert_f64_eq(format_sum("1.234,56").unwrap(), 1234.56);
+        assert_f64_eq(format_sum("1.234.567,89").unwrap(), 1_234_567.89);
+        assert_f64_eq(format_sum("12 345 678").unwrap(), 12_345_678.0);
+        assert_f64_eq(format_sum("-1 234,56").unwrap(), -1234.56);
+        assert_f64_eq(format_sum("-12,30").unwrap(), -12.30);
+        assert_f64_eq(format_sum("0,00").unwrap(), 0.0);
+    }
+
+    #[test]
+    fn test_decimal_point() {
+        assert_f64_eq(format_sum("12.50").unwrap(), 12.50);
+        assert_f64_eq(format_sum("1.5").unwrap(), 1.5);
+    }
+
+    #[test]
+    fn test_ambiguous_or_invalid_separators() {
+        for value in [
+            "1.234",
+            "1,234,56",
+            "12 34,56",
+            "1234 567,00",
+            "1.234 567,00",
+            "1,2a",
+            "",
+            "-",
+            ",50",
+            "1 234,5 6",
+        ] {
+            assert!(format_sum(value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn test_finnish_round_trip() {
+        for value in ["1234,56", "-12,30", "0,00", "0,01", "99999,99"] {
+            assert_eq!(Locale::Fi.format_sum(format_sum(value).unwrap()), value);
+        }
+        assert_eq!(Locale::Fi.format_sum(format_sum("1 234,56").unwrap()), "1234,56");
+        assert_eq!(Locale::Fi.format_sum(format_sum("1.234,56").unwrap()), "1234,56");
+    }
+}
+
+#[cfg(test)]
+mod test_clean_name {
+    use super::*;
+
+    #[test]
+    fn test_clean_name_table() {
+        let cases = [
+            // Whitespace, separators and prefixes
+            ("k-market", "K-MARKET"),
+            ("  Some\tShop \r\n Helsinki  ", "SOME SHOP HELSINKI"),
+            ("Osto KAHVILA", "KAHVILA"),
+            ("TC*SHOP", "SHOP"),
+            ("SHOP*ONE/TWO_THREE", "SHOP ONE TWO THREE"),
+            ("H&amp;M", "H&M"),
+            ("h&AMP;m", "H&M"),
+            ("SHOP (HELSINKI) [1] {2}", "SHOP HELSINKI 1 2"),
+            // REPLACE_CONTAINS
+            ("KAUPPA EPASSI OY", "EPASSI"),
+            ("FINNAIR 1234", "FINNAIR"),
+            ("JASEN IDA RADIO RY 2024", "IDA RADIO RY"),
+            ("APPLE.COM/BILL ITUNES.COM", "APPLE ITUNES"),
+            ("K-CITYMARKET ESPOO", "K-MARKET"),
+            ("WWW.VERKKOKAUPPA.COM HELSINKI", "VERKKOKAUPPA.COM"),
+            ("WOLT HELSINKI", "WOLT"),
+            // REPLACE_PAIRS
+            ("PAYPAL 4029357733 SHOP", "PAYPAL SHOP"),
+            ("SHOP - HELSINKI", "SHOP HELSINKI"),
+            ("SHOP . HELSINKI", "SHOP HELSINKI"),
+            ("SHOP, HELSINKI", "SHOP HELSINKI"),
+            ("PAYPAL THOMANN 35314369001", "PAYPAL THOMANN"),
+            ("PAYPAL SHOP 402-935-7733", "PAYPAL SHOP"),
+            ("SHOP DRI CHARGE", "SHOPCHARGE"),
+            ("STORE LEVISTRAUSS FINLAND", "STORE LEVIS FINLAND"),
+            ("EXAMPLE.COMFI", "EXAMPLE.COM"),
+            ("CHATGPT SUBSCRIPTION HTTPSOPENAI.C", "CHATGPT SUBSCRIPTION OPENAI.COM"),
+            ("VFI SHOP", "SHOP"),
+            // REPLACE_START_PAIRS
+            ("CHF SHOP", "SHOP"),
+            ("CHFSHOP", "SHOP"),
+            ("WWW.EXAMPLE.COM", "EXAMPLE.COM"),
+            ("MOB.PAY KAHVILA", "MOBILEPAY KAHVILA"),
+            // REPLACE_START
+            ("ALEPA KAMPPI", "ALEPA"),
+            ("BEAMHILL 123", "BEAMHILL"),
+            ("HERTZ AIRPORT", "HERTZ"),
+            ("K-MARKET KAMPPI", "K-MARKET"),
+            ("PAYPAL *BANDCAMP 123", "PAYPAL BANDCAMP"),
+            ("PAYPAL *BEATPORT 123", "PAYPAL BEATPORT"),
+            ("PAYPAL *DJCITY 123", "PAYPAL DJCITY"),
+            ("PAYPAL *DROPBOX 123", "PAYPAL DROPBOX"),
+            ("PAYPAL *MISTERB 123", "PAYPAL MISTERB"),
+            ("PAYPAL *PATREON 123", "PAYPAL PATREON"),
+            ("STOCKMANN HELSINKI", "STOCKMANN"),
+            // Unchanged
+            ("", ""),
+            ("PAYPAL NIKE COM", "PAYPAL NIKE COM"),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(clean_name(raw), expected, "raw name: {raw:?}");
+        }
+    }
+
+    #[test]
+    fn test_clean_name_is_idempotent() {
+        for raw in [
+            "Osto PAYPAL *THOMANN 35314369001",
+            "MOB.PAY KAHVILA",
+            "H&amp;M (Kamppi)",
+        ] {
+            let cleaned = clean_name(raw);
+            assert_eq!(clean_name(&cleaned), cleaned);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_golden {
+    use super::*;
+
+    use std::fs;
+
+    /// Set to update the golden file after an intended change in the output.
+    const UPDATE_ENV: &str = "UPDATE_GOLDEN";
+
+    fn fixture_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("fixtures")
+            .join("visa_parse")
+    }
+
+    #[test]
+    fn test_csv_matches_golden_file() {
+        let items = parse_file(&fixture_dir().join("finvoice.xml")).unwrap();
+        let csv = format_csv(&items, Locale::default());
+        let golden = fixture_dir().join("VISA.csv");
+        if std::env::var_os(UPDATE_ENV).is_some() {
+            fs::write(&golden, &csv).unwrap();
+        }
+        assert_eq!(
+            csv,
+            fs::read_to_string(&golden).unwrap(),
+            "CSV output changed, run the tests with {UPDATE_ENV}=1 to update the golden file if intended"
+        );
+    }
+
+    #[test]
+    fn test_excel_from_fixture() {
+        let dir = tempfile::tempdir().unwrap();
+        let items = parse_file(&fixture_dir().join("finvoice.xml")).unwrap();
+        let totals = calculate_totals_for_each_name(&items);
+        write_to_excel(&items, &totals, dir.path(), 3, Locale::Fi).unwrap();
+        assert!(dir.path().join("VISA.xlsx").is_file());
+    }
 }
 
 #[cfg(test)]
@@ -751,3 +1445,302 @@ mod test_item_parse {
         assert_eq!(three, "443,44");
     }
 }
+
+#[cfg(test)]
+mod test_read_file {
+    use super::*;
+
+    use std::fs;
+
+    const FINVOICE: &str = r#"<?xml version="1.0" encoding="ISO-8859-15"?>
+<Finvoice Version="1.3">
+  <InvoiceDetails>
+    <StartDate Format="CCYYMMDD">20240501</StartDate>
+  </InvoiceDetails>
+  <SpecificationDetails>
+    <SpecificationFreeText>25.05. Osto PAYPAL *THOMANN 35314369001                                 1 488,90</SpecificationFreeText>
+    <SpecificationFreeText>30.05. Osto PAYPAL *NIKE COM 35314369001                                  443,44</SpecificationFreeText>
+  </SpecificationDetails>
+</Finvoice>
+"#;
+
+    fn truncated_finvoice() -> &'static str {
+        &FINVOICE[..FINVOICE.find("<SpecificationFreeText>30.05.").unwrap()]
+    }
+
+    #[test]
+    fn test_read_complete_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("complete.xml");
+        fs::write(&file, FINVOICE).unwrap();
+
+        let (lines, year) = read_xml_file(&file).unwrap();
+        assert_eq!(year, 2024);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(parse_file(&file).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_read_truncated_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("truncated.xml");
+        fs::write(&file, truncated_finvoice()).unwrap();
+
+        assert!(read_xml_file(&file).is_err());
+    }
+
+    #[test]
+    fn test_read_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(read_xml_file(&dir.path().join("missing.xml")).is_err());
+    }
+
+    #[test]
+    fn test_parse_files_skips_failed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let complete = dir.path().join("complete.xml");
+        let truncated = dir.path().join("truncated.xml");
+        fs::write(&complete, FINVOICE).unwrap();
+        fs::write(&truncated, truncated_finvoice()).unwrap();
+        let files = vec![complete, truncated.clone()];
+
+        let (items, parsed_files) = parse_files(dir.path(), files.clone(), false, false, Locale::Fi).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(parsed_files, vec![files[0].clone()]);
+        assert_eq!(
+            filter_new_files(files.clone(), &parsed_files.into_iter().collect()),
+            vec![truncated.clone()]
+        );
+
+        assert!(parse_files(dir.path(), files, false, true, Locale::Fi).is_err());
+        assert!(parse_files(dir.path(), vec![truncated], false, false, Locale::Fi).is_err());
+    }
+
+    #[test]
+    fn test_new_items_output_path() {
+        assert_eq!(
+            new_items_output_path(Path::new("reports")),
+            Path::new("reports").join("VISA.new.csv")
+        );
+        assert_eq!(
+            new_items_output_path(Path::new("reports/visa.csv")),
+            Path::new("reports/visa.new.csv")
+        );
+        assert_eq!(
+            new_items_output_path(Path::new("reports/visa.XLSX")),
+            Path::new("reports/visa.new.XLSX")
+        );
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_millis(1500)), "1.5s");
+        assert_eq!(format_duration(Duration::from_secs(65)), "1m 05s");
+    }
+
+    #[test]
+    fn test_format_progress() {
+        assert_eq!(
+            format_progress(Duration::from_secs(10), 5, 10),
+            "[10.0s elapsed, ~10.0s remaining]"
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_write_csv {
+    use super::*;
+
+    use cli_tools::assert_f64_eq;
+
+    /// Parse CSV back to date, sum, and name columns.
+    fn parse_csv(content: &str, locale: Locale) -> Vec<(String, f64, String)> {
+        let delimiter = locale.csv_delimiter();
+        content
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let mut parts = line.splitn(3, delimiter);
+                let date = parts.next().unwrap().to_string();
+                let sum = parts.next().unwrap().replace(',', ".").parse::<f64>().unwrap();
+                let name = parts.next().unwrap();
+                let name = name
+                    .strip_prefix('"')
+                    .and_then(|name| name.strip_suffix('"'))
+                    .map_or_else(|| name.to_string(), |name| name.replace("\"\"", "\""));
+                (date, sum, name)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        let items = vec![
+            VisaItem {
+                date: NaiveDate::from_ymd_opt(2024, 1, 15).unwrap(),
+                name: "K-MARKET".to_string(),
+                sum: 12.5,
+            },
+            VisaItem {
+                date: NaiveDate::from_ymd_opt(2024, 1, 16).unwrap(),
+                name: "SHOP, HELSINKI; \"OY\"".to_string(),
+                sum: 1234.56,
+            },
+        ];
+        for locale in [Locale::Fi, Locale::En, Locale::Legacy] {
+            let csv = format_csv(&items, locale);
+            let header: Vec<&str> = csv.lines().next().unwrap().split(locale.csv_delimiter()).collect();
+            let [date, name, sum] = locale.headers();
+            assert_eq!(header, vec![date, sum, name]);
+
+            let rows = parse_csv(&csv, locale);
+            assert_eq!(rows.len(), items.len());
+            for ((row_date, row_sum, row_name), item) in rows.iter().zip(&items) {
+                assert_eq!(*row_date, locale.format_date(item.date));
+                assert_f64_eq(*row_sum, item.sum);
+                assert_eq!(*row_name, item.name);
+            }
+        }
+        assert!(format_csv(&items, Locale::Fi).contains("2024.01.15;12,50;K-MARKET"));
+        assert!(format_csv(&items, Locale::En).contains("2024-01-15,12.50,K-MARKET"));
+        let csv = format_csv(&items, Locale::Legacy);
+        assert!(csv.starts_with("Date,Sum,Name\n2024.01.15,12.50,K-MARKET\n"));
+    }
+
+    #[test]
+    fn test_item_display_follows_locale() {
+        let item = VisaItem {
+            date: NaiveDate::from_ymd_opt(2024, 1, 15).unwrap(),
+            name: "K-MARKET".to_string(),
+            sum: 12.5,
+        };
+        assert_eq!(item.display(Locale::Fi), "2024.01.15     12,50€   K-MARKET");
+        assert_eq!(item.display(Locale::En), "2024-01-15     12.50€   K-MARKET");
+        assert_eq!(item.display(Locale::Legacy), item.display(Locale::Fi));
+        assert_eq!(Locale::default(), Locale::Legacy);
+    }
+}
+
+#[cfg(test)]
+mod test_write_excel {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_sheet_name() {
+        let mut used: HashSet<String> = RESERVED_SHEET_NAMES.iter().map(|name| name.to_lowercase()).collect();
+        assert_eq!(sanitize_sheet_name("K-MARKET", &mut used), "K-MARKET");
+        assert_eq!(sanitize_sheet_name("k-market", &mut used), "k-market (2)");
+        assert_eq!(sanitize_sheet_name("A/B: [C]*?", &mut used), "A_B_ _C___");
+        assert_eq!(sanitize_sheet_name("'Quoted'", &mut used), "Quoted");
+        assert_eq!(sanitize_sheet_name("misc", &mut used), "misc (2)");
+        assert_eq!(sanitize_sheet_name("", &mut used), "Sheet");
+
+        let long_name = "A VERY LONG MERCHANT NAME THAT DOES NOT FIT";
+        let first = sanitize_sheet_name(long_name, &mut used);
+        let second = sanitize_sheet_name(long_name, &mut used);
+        assert_eq!(first, "A VERY LONG MERCHANT NAME THAT");
+        assert_eq!(second, "A VERY LONG MERCHANT NAME T (2)");
+        assert!(second.chars().count() <= MAX_SHEET_NAME_LENGTH);
+    }
+
+    #[test]
+    fn test_merchant_sheet_names() {
+        let totals = vec![
+            ("SHOP".to_string(), 30.0),
+            ("Shop".to_string(), 20.0),
+            ("CAFE".to_string(), 10.0),
+        ];
+        let names = merchant_sheet_names(&totals, 2);
+        assert_eq!(names.len(), 2);
+        assert_eq!(names["SHOP"], "SHOP");
+        assert_eq!(names["Shop"], "Shop (2)");
+        assert!(merchant_sheet_names(&totals, 0).is_empty());
+    }
+
+    #[test]
+    fn test_write_excel_with_merchant_sheets() {
+        let dir = tempfile::tempdir().unwrap();
+        let items: Vec<VisaItem> = (0..300)
+            .map(|index| VisaItem {
+                date: NaiveDate::from_ymd_opt(2024, 1, 1 + index % 28).unwrap(),
+                name: format!("MERCHANT {}", index % 15),
+                sum: f64::from(index),
+            })
+            .collect();
+        let totals = calculate_totals_for_each_name(&items);
+        write_to_excel(&items, &totals, dir.path(), 5, Locale::Fi).unwrap();
+        assert!(dir.path().join("VISA.xlsx").is_file());
+    }
+}
+
+#[cfg(test)]
+mod test_compare_months {
+    use super::*;
+
+    fn item(year: i32, month: u32, sum: f64) -> VisaItem {
+        VisaItem {
+            date: NaiveDate::from_ymd_opt(year, month, 1).unwrap(),
+            name: "SHOP".to_string(),
+            sum,
+        }
+    }
+
+    #[test]
+    fn test_no_items() {
+        assert!(compare_months(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_single_month() {
+        let comparisons = compare_months(&[item(2024, 5, 10.0), item(2024, 5, 5.5)]);
+        assert_eq!(
+            comparisons,
+            vec![MonthComparison {
+                year: 2024,
+                month: 5,
+                total: 15.5,
+                previous_month: None,
+                previous_year: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_previous_month_and_year() {
+        let items = [
+            item(2024, 1, 100.0),
+            item(2024, 12, 80.0),
+            item(2025, 1, 50.0),
+            item(2025, 1, 25.0),
+        ];
+        let comparisons = compare_months(&items);
+        assert_eq!(comparisons.len(), 3);
+        assert_eq!((comparisons[0].year, comparisons[0].month), (2024, 1));
+        assert_eq!(comparisons[0].previous_month, None);
+        assert_eq!(comparisons[0].previous_year, None);
+        // December is compared to November, which has no data
+        assert_eq!(comparisons[1].previous_month, None);
+        assert_eq!(comparisons[1].previous_year, None);
+        // January is compared to December of the previous year
+        assert_eq!((comparisons[2].year, comparisons[2].month), (2025, 1));
+        cli_tools::assert_f64_eq(comparisons[2].total, 75.0);
+        assert_eq!(comparisons[2].previous_month, Some(80.0));
+        assert_eq!(comparisons[2].previous_year, Some(100.0));
+    }
+
+    #[test]
+    fn test_missing_month_in_between() {
+        let comparisons = compare_months(&[item(2024, 3, 10.0), item(2024, 5, 20.0)]);
+        assert_eq!(comparisons.len(), 2);
+        assert_eq!(comparisons[1].previous_month, None);
+    }
+
+    #[test]
+    fn test_format_change() {
+        assert!(format_change(110.0, Some(100.0), Locale::En).contains("+10.00€ (+10.0%)"));
+        assert!(format_change(75.0, Some(100.0), Locale::Fi).contains("-25,00€ (-25,0%)"));
+        assert!(format_change(10.0, Some(0.0), Locale::En).contains("+10.00€"));
+        assert!(!format_change(10.0, Some(0.0), Locale::En).contains('%'));
+        assert_eq!(format_change(10.0, None, Locale::En).trim(), "-");
+    }
+}
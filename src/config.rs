@@ -1,17 +1,121 @@
-use std::path::PathBuf;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::LazyLock;
 
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+
 const PROJECT_NAME: &str = env!("CARGO_PKG_NAME");
 
-pub static CONFIG_PATH: LazyLock<Option<PathBuf>> = LazyLock::new(|| {
-    let home_dir = dirs::home_dir()?;
+/// Environment variable for overriding the user config file path.
+pub const CONFIG_PATH_ENV: &str = "CLI_TOOLS_CONFIG";
+
+pub static CONFIG_PATH: LazyLock<Option<PathBuf>> = LazyLock::new(find_config_path);
+
+/// Locate the user config file.
+///
+/// Uses the path from the `CLI_TOOLS_CONFIG` environment variable if it is set.
+/// Otherwise, looks for `$HOME/.config/<PROJECT_NAME>.toml`,
+/// and then for `<PROJECT_NAME>.toml` in the platform config directory,
+/// for example `%APPDATA%` on Windows.
+fn find_config_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os(CONFIG_PATH_ENV).filter(|path| !path.is_empty()) {
+        return Some(PathBuf::from(path));
+    }
+
+    let file_name = format!("{PROJECT_NAME}.toml");
+    let home_config = dirs::home_dir().map(|home| home.join(".config").join(&file_name));
+    let platform_config = dirs::config_dir().map(|dir| dir.join(&file_name));
+
+    [home_config, platform_config]
+        .into_iter()
+        .flatten()
+        .find(|path| path.exists())
+}
+
+/// Load the config section for the given tool from the user config file.
+///
+/// Returns `None` if there is no config file or it does not have a section for the tool.
+/// Fails if the file can not be read or the section contains invalid values,
+/// with the file path in the error message.
+pub fn load_tool_config<T: DeserializeOwned>(tool_name: &str) -> Result<Option<T>> {
+    CONFIG_PATH
+        .as_deref()
+        .map_or(Ok(None), |path| load_tool_config_from_path(path, tool_name))
+}
+
+/// Load the config section for the given tool from the given config file.
+pub fn load_tool_config_from_path<T: DeserializeOwned>(path: &Path, tool_name: &str) -> Result<Option<T>> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read config file: {}", path.display()))?;
+    parse_tool_config(&content, tool_name).with_context(|| format!("Invalid config file: {}", path.display()))
+}
+
+/// Parse the `[tool_name]` section from the config file contents.
+fn parse_tool_config<T: DeserializeOwned>(content: &str, tool_name: &str) -> Result<Option<T>> {
+    let mut table: toml::Table = toml::from_str(content)?;
+    table
+        .remove(tool_name)
+        .map(|section| {
+            section
+                .try_into()
+                .with_context(|| format!("Invalid [{tool_name}] section"))
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod config_tests {
+    use super::*;
+
+    use serde::Deserialize;
+
+    #[derive(Debug, Default, Deserialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    struct TestConfig {
+        #[serde(default)]
+        verbose: bool,
+        #[serde(default)]
+        names: Vec<String>,
+    }
+
+    #[test]
+    fn test_parse_tool_section() {
+        let content = "[other]\nunknown = 1\n\n[test]\nverbose = true\nnames = [\"a\"]\n";
+        let config: Option<TestConfig> = parse_tool_config(content, "test").unwrap();
+        assert_eq!(
+            config,
+            Some(TestConfig {
+                verbose: true,
+                names: vec!["a".to_string()],
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_missing_section() {
+        let config: Option<TestConfig> = parse_tool_config("[other]\nverbose = true\n", "test").unwrap();
+        assert_eq!(config, None);
+    }
+
+    #[test]
+    fn test_parse_unknown_field_names_key() {
+        let error = parse_tool_config::<TestConfig>("[test]\nverbos = true\n", "test").unwrap_err();
+        let message = format!("{error:#}");
+        assert!(message.contains("[test]"), "{message}");
+        assert!(message.contains("verbos"), "{message}");
+    }
 
-    // Config file path: "$HOME/.config/<PROJECT_NAME>.toml"
-    let config_path = home_dir.join(".config").join(format!("{PROJECT_NAME}.toml"));
+    #[test]
+    fn test_load_reports_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "[test]\nverbose = \"yes\"\n").unwrap();
+        let error = load_tool_config_from_path::<TestConfig>(&path, "test").unwrap_err();
+        assert!(format!("{error:#}").contains(&path.display().to_string()));
 
-    if config_path.exists() {
-        Some(config_path)
-    } else {
-        None
+        let missing = dir.path().join("missing.toml");
+        assert!(load_tool_config_from_path::<TestConfig>(&missing, "test").is_err());
     }
-});
+}
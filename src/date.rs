@@ -0,0 +1,81 @@
+use std::sync::LazyLock;
+
+use chrono::NaiveDate;
+use regex::Regex;
+
+/// Date with day first: "dd.mm.yyyy".
+pub static RE_DD_MM_YYYY: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?P<day>0*[1-9]\d?)\.(?P<month>0*[1-9]\d?)\.(?P<year>0*[1-9]\d{3})")
+        .expect("Failed to create regex pattern for dd.mm.yyyy")
+});
+
+/// Date with year first: "yyyy.mm.dd".
+pub static RE_YYYY_MM_DD: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?P<year>0*[1-9]\d{3})\.(?P<month>0*[1-9]\d?)\.(?P<day>0*[1-9]\d?)")
+        .expect("Failed to create regex pattern for yyyy.mm.dd")
+});
+
+/// Date with day first using any common separator.
+static RE_DAY_FIRST: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:^|\D)(?P<day>\d{1,2})[._-](?P<month>\d{1,2})[._-](?P<year>\d{4})(?:\D|$)")
+        .expect("Failed to create regex pattern for day first date")
+});
+
+/// Date with year first using any common separator.
+static RE_YEAR_FIRST: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:^|\D)(?P<year>\d{4})[._-](?P<month>\d{1,2})[._-](?P<day>\d{1,2})(?:\D|$)")
+        .expect("Failed to create regex pattern for year first date")
+});
+
+/// Range of years that are considered to be dates and not other numbers.
+const YEAR_RANGE: std::ops::RangeInclusive<i32> = 1900..=2100;
+
+/// Check if the name contains a valid date in either day first or year first order.
+///
+/// Dots, dashes, and underscores are accepted as separators.
+///
+/// ```rust
+/// use cli_tools::date::contains_date;
+///
+/// assert!(contains_date("Photos.2023.12.24"));
+/// assert!(contains_date("report_24-12-2023"));
+/// assert!(!contains_date("Movie.1080p.x265"));
+/// ```
+#[must_use]
+pub fn contains_date(name: &str) -> bool {
+    [&*RE_DAY_FIRST, &*RE_YEAR_FIRST].iter().any(|regex| {
+        regex.captures_iter(name).any(|caps| {
+            let year = caps.name("year").and_then(|m| m.as_str().parse::<i32>().ok());
+            let month = caps.name("month").and_then(|m| m.as_str().parse::<u32>().ok());
+            let day = caps.name("day").and_then(|m| m.as_str().parse::<u32>().ok());
+            match (year, month, day) {
+                (Some(year), Some(month), Some(day)) => {
+                    YEAR_RANGE.contains(&year) && NaiveDate::from_ymd_opt(year, month, day).is_some()
+                }
+                _ => false,
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod date_tests {
+    use super::*;
+
+    #[test]
+    fn test_contains_date() {
+        assert!(contains_date("2023.12.24"));
+        assert!(contains_date("Photos 2023-1-2 trip"));
+        assert!(contains_date("meeting_24.12.2023.txt"));
+        assert!(contains_date("IMG_2024_02_29"));
+    }
+
+    #[test]
+    fn test_does_not_contain_date() {
+        assert!(!contains_date("Some.File"));
+        assert!(!contains_date("2023.13.01"));
+        assert!(!contains_date("2023.02.30"));
+        assert!(!contains_date("1080.11.20"));
+        assert!(!contains_date("12345.01.01"));
+    }
+}
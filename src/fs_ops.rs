@@ -0,0 +1,262 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+
+/// File system operations that change files on disk.
+///
+/// Tools take this as a parameter instead of calling [`std::fs`] directly,
+/// so a dry run goes through exactly the same code path as a real run.
+pub trait FsOps {
+    /// Rename or move a file or directory.
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+
+    /// Create a directory and all missing parent directories.
+    fn create_dir_all(&self, path: &Path) -> Result<()>;
+
+    /// Remove a file or an empty directory.
+    fn remove(&self, path: &Path) -> Result<()>;
+
+    /// Move a file or directory to the trash so it can be restored.
+    fn trash(&self, path: &Path) -> Result<()>;
+
+    /// Set the modification time of a file.
+    fn set_mtime(&self, path: &Path, time: SystemTime) -> Result<()>;
+}
+
+/// A file system operation recorded by [`DryRunFs`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsOp {
+    Rename { from: PathBuf, to: PathBuf },
+    CreateDirAll(PathBuf),
+    Remove(PathBuf),
+    Trash(PathBuf),
+    SetMtime { path: PathBuf, time: SystemTime },
+}
+
+/// Performs the operations on the real file system.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RealFs;
+
+/// Records the operations without touching the file system.
+#[derive(Debug, Default)]
+pub struct DryRunFs {
+    operations: RefCell<Vec<FsOp>>,
+}
+
+impl FsOps for RealFs {
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        fs::rename(from, to).with_context(|| format!("Failed to rename {} to {}", from.display(), to.display()))
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        fs::create_dir_all(path).with_context(|| format!("Failed to create directory: {}", path.display()))
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        if path.is_dir() {
+            fs::remove_dir(path).with_context(|| format!("Failed to remove directory: {}", path.display()))
+        } else {
+            fs::remove_file(path).with_context(|| format!("Failed to remove file: {}", path.display()))
+        }
+    }
+
+    fn trash(&self, path: &Path) -> Result<()> {
+        crate::trash::move_to_trash(path)
+    }
+
+    fn set_mtime(&self, path: &Path, time: SystemTime) -> Result<()> {
+        crate::set_modified_time(path, time)
+    }
+}
+
+impl DryRunFs {
+    /// Create an empty operation log.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get a copy of the recorded operations in order.
+    #[must_use]
+    pub fn operations(&self) -> Vec<FsOp> {
+        self.operations.borrow().clone()
+    }
+
+    /// Consume the log and return the recorded operations in order.
+    #[must_use]
+    pub fn into_operations(self) -> Vec<FsOp> {
+        self.operations.into_inner()
+    }
+
+    /// Print the recorded operations, one per line.
+    pub fn print(&self) {
+        for operation in self.operations.borrow().iter() {
+            println!("{operation}");
+        }
+    }
+
+    fn record(&self, operation: FsOp) {
+        self.operations.borrow_mut().push(operation);
+    }
+}
+
+impl FsOps for DryRunFs {
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.record(FsOp::Rename {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        });
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        self.record(FsOp::CreateDirAll(path.to_path_buf()));
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        self.record(FsOp::Remove(path.to_path_buf()));
+        Ok(())
+    }
+
+    fn trash(&self, path: &Path) -> Result<()> {
+        self.record(FsOp::Trash(path.to_path_buf()));
+        Ok(())
+    }
+
+    fn set_mtime(&self, path: &Path, time: SystemTime) -> Result<()> {
+        self.record(FsOp::SetMtime {
+            path: path.to_path_buf(),
+            time,
+        });
+        Ok(())
+    }
+}
+
+impl fmt::Display for FsOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rename { from, to } => write!(f, "rename: {} -> {}", from.display(), to.display()),
+            Self::CreateDirAll(path) => write!(f, "create directory: {}", path.display()),
+            Self::Remove(path) => write!(f, "remove: {}", path.display()),
+            Self::Trash(path) => write!(f, "move to trash: {}", path.display()),
+            Self::SetMtime { path, time } => write!(
+                f,
+                "set modification time: {} -> {}",
+                path.display(),
+                DateTime::<Local>::from(*time).format("%Y-%m-%d %H:%M:%S")
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod fs_ops_tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    fn modified_time() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_001)
+    }
+
+    /// Move a file into a new subdirectory and remove the old directory.
+    fn move_into_subdirectory(fs_ops: &impl FsOps, root: &Path) -> Result<()> {
+        let target = root.join("target");
+        fs_ops.create_dir_all(&target)?;
+        fs_ops.rename(&root.join("source").join("file.txt"), &target.join("file.txt"))?;
+        fs_ops.set_mtime(&target.join("file.txt"), modified_time())?;
+        fs_ops.remove(&root.join("source"))
+    }
+
+    #[test]
+    fn test_dry_run_records_trash() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file.txt");
+        fs::write(&file, "content").unwrap();
+
+        let dry_run = DryRunFs::new();
+        dry_run.trash(&file).unwrap();
+        assert!(file.is_file());
+        assert_eq!(
+            dry_run.into_operations()[0].to_string(),
+            format!("move to trash: {}", file.display())
+        );
+    }
+
+    fn create_source(root: &Path) {
+        fs::create_dir_all(root.join("source")).unwrap();
+        fs::write(root.join("source").join("file.txt"), "content").unwrap();
+    }
+
+    #[test]
+    fn test_dry_run_records_operations() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        create_source(root);
+
+        let dry_run = DryRunFs::new();
+        move_into_subdirectory(&dry_run, root).unwrap();
+        assert_eq!(
+            dry_run.operations(),
+            vec![
+                FsOp::CreateDirAll(root.join("target")),
+                FsOp::Rename {
+                    from: root.join("source").join("file.txt"),
+                    to: root.join("target").join("file.txt"),
+                },
+                FsOp::SetMtime {
+                    path: root.join("target").join("file.txt"),
+                    time: modified_time(),
+                },
+                FsOp::Remove(root.join("source")),
+            ]
+        );
+        assert!(root.join("source").join("file.txt").is_file());
+        assert!(!root.join("target").exists());
+        assert_eq!(
+            dry_run.into_operations()[0].to_string(),
+            format!("create directory: {}", root.join("target").display())
+        );
+    }
+
+    #[test]
+    fn test_real_fs_applies_operations() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        create_source(root);
+
+        move_into_subdirectory(&RealFs, root).unwrap();
+        let file = root.join("target").join("file.txt");
+        assert_eq!(fs::read_to_string(&file).unwrap(), "content");
+        assert_eq!(fs::metadata(&file).unwrap().modified().unwrap(), modified_time());
+        assert!(!root.join("source").exists());
+    }
+
+    #[test]
+    fn test_real_fs_sets_mtime_of_read_only_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file.txt");
+        fs::write(&file, "content").unwrap();
+        let mut permissions = fs::metadata(&file).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&file, permissions).unwrap();
+
+        RealFs.set_mtime(&file, modified_time()).unwrap();
+        assert_eq!(fs::metadata(&file).unwrap().modified().unwrap(), modified_time());
+    }
+
+    #[test]
+    fn test_real_fs_errors_have_context() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.txt");
+        let error = RealFs.rename(&missing, &dir.path().join("other.txt")).unwrap_err();
+        assert!(error.to_string().starts_with("Failed to rename"));
+        assert!(RealFs.remove(&missing).is_err());
+    }
+}
@@ -1,23 +1,57 @@
 pub mod config;
+pub mod date;
+pub mod fs_ops;
+pub mod lock;
+pub mod output;
+pub mod state;
+pub mod tokens;
+pub mod trash;
 
+use std::cmp::Ordering;
 use std::env;
 use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
 use anyhow::{Context, Result};
 use colored::{ColoredString, Colorize};
 use difference::{Changeset, Difference};
 use unicode_normalization::UnicodeNormalization;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 use walkdir::DirEntry;
 
 /// Append an extension to `PathBuf`, which is missing from the standard lib :(
+///
+/// A leading dot in the extension is ignored, so both "nfd" and ".nfd" give "file.txt.nfd".
 pub fn append_extension_to_path(path: PathBuf, extension: impl AsRef<OsStr>) -> PathBuf {
+    let extension = extension.as_ref();
+    let extension = extension
+        .to_str()
+        .and_then(|ext| ext.strip_prefix('.'))
+        .map_or(extension, OsStr::new);
     let mut os_string: OsString = path.into();
     os_string.push(".");
     os_string.push(extension);
     os_string.into()
 }
 
+/// Insert a suffix to the end of the file stem, keeping the extension.
+///
+/// "video.mp4" with suffix ".x265" becomes "video.x265.mp4".
+/// Invalid Unicode in the file name is dropped.
+#[must_use]
+pub fn insert_suffix_before_extension(path: &Path, suffix: &str) -> PathBuf {
+    let file_stem = path.file_stem().map(os_str_to_string).unwrap_or_default();
+    let file_name = path.extension().map_or_else(
+        || format!("{file_stem}{suffix}"),
+        |extension| format!("{file_stem}{suffix}.{}", os_str_to_string(extension)),
+    );
+    path.with_file_name(file_name)
+}
+
 /// Format bool value as a coloured string.
 #[must_use]
 pub fn colorize_bool(value: bool) -> ColoredString {
@@ -53,12 +87,104 @@ pub fn get_normalized_dir_name(path: &Path) -> Result<String> {
     Ok(dir_name.nfc().collect::<String>())
 }
 
+/// Directory names created by operating systems that should not be processed.
+pub const SYSTEM_DIRECTORIES: [&str; 9] = [
+    "$RECYCLE.BIN",
+    ".Spotlight-V100",
+    ".Trash",
+    ".Trashes",
+    ".fseventsd",
+    "@eaDir",
+    "System Volume Information",
+    "__MACOSX",
+    "lost+found",
+];
+
+/// Version control metadata directory names.
+pub const VCS_DIRECTORIES: [&str; 3] = [".git", ".hg", ".svn"];
+
+/// Rules for which directory entries to skip when walking a directory tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkipRules {
+    /// Skip hidden files and directories (name starts with '.')
+    pub skip_hidden: bool,
+    /// Skip operating system directories like the recycle bin
+    pub skip_system_dirs: bool,
+    /// Skip files and directories with any of these exact names
+    pub skip_names: Vec<String>,
+    /// Skip files with any of these extensions (case-insensitive, without the leading dot)
+    pub skip_extensions: Vec<String>,
+    /// Skip files and directories whose name contains any of these patterns
+    pub exclude: Vec<String>,
+    /// Skip directories whose name contains any of these patterns
+    pub exclude_dirs: Vec<String>,
+}
+
+impl Default for SkipRules {
+    fn default() -> Self {
+        Self {
+            skip_hidden: true,
+            skip_system_dirs: true,
+            skip_names: Vec::new(),
+            skip_extensions: Vec::new(),
+            exclude: Vec::new(),
+            exclude_dirs: Vec::new(),
+        }
+    }
+}
+
+impl SkipRules {
+    /// Check if the entry should be skipped according to these rules.
+    #[must_use]
+    pub fn should_skip(&self, entry: &DirEntry) -> bool {
+        if self.skip_hidden && is_hidden(entry) {
+            return true;
+        }
+        let name = entry.file_name().to_string_lossy();
+        if self.skip_system_dirs && entry.file_type().is_dir() && is_system_directory(&name) {
+            return true;
+        }
+        if self.skip_names.iter().any(|skip| *skip == name) {
+            return true;
+        }
+        if self.exclude.iter().any(|pattern| name.contains(pattern.as_str())) {
+            return true;
+        }
+        if entry.file_type().is_dir() && self.exclude_dirs.iter().any(|pattern| name.contains(pattern.as_str())) {
+            return true;
+        }
+        if entry.file_type().is_file() && !self.skip_extensions.is_empty() {
+            if let Some(extension) = entry.path().extension() {
+                let extension = extension.to_string_lossy();
+                return self
+                    .skip_extensions
+                    .iter()
+                    .any(|skip| skip.trim_start_matches('.').eq_ignore_ascii_case(&extension));
+            }
+        }
+        false
+    }
+}
+
 /// Check if entry is a hidden file or directory (starts with '.')
 #[must_use]
 pub fn is_hidden(entry: &DirEntry) -> bool {
     entry.file_name().to_str().is_some_and(|s| s.starts_with('.'))
 }
 
+/// Check if the directory name is an operating system directory like the recycle bin.
+#[must_use]
+pub fn is_system_directory(name: &str) -> bool {
+    SYSTEM_DIRECTORIES.iter().any(|dir| dir.eq_ignore_ascii_case(name))
+}
+
+/// Check if entry should be skipped using the default [`SkipRules`]:
+/// hidden files and directories, and operating system directories.
+#[must_use]
+pub fn should_skip_entry(entry: &DirEntry) -> bool {
+    SkipRules::default().should_skip(entry)
+}
+
 /// Resolves the provided input path to a directory or file to an absolute path.
 ///
 /// If `path` is `None` or an empty string, the current working directory is used.
@@ -89,6 +215,24 @@ pub fn resolve_input_path(path: Option<&str>) -> Result<PathBuf> {
     Ok(absolute_input_path)
 }
 
+/// Resolves multiple input paths to absolute paths.
+///
+/// Uses the current working directory if no paths are given.
+/// Duplicate paths are only included once, keeping the first occurrence.
+pub fn resolve_input_paths(paths: &[String]) -> Result<Vec<PathBuf>> {
+    if paths.is_empty() {
+        return Ok(vec![resolve_input_path(None)?]);
+    }
+    let mut resolved: Vec<PathBuf> = Vec::with_capacity(paths.len());
+    for path in paths {
+        let path = resolve_input_path(Some(path))?;
+        if !resolved.contains(&path) {
+            resolved.push(path);
+        }
+    }
+    Ok(resolved)
+}
+
 /// Resolves the provided output path relative to an absolute input path.
 ///
 /// If `path` is provided, it is used directly.
@@ -114,6 +258,170 @@ pub fn resolve_output_path(path: Option<&str>, absolute_input_path: &Path) -> Re
     Ok(output_path)
 }
 
+/// Copy a file and apply the source modification time and permissions to the copy.
+///
+/// Returns the number of bytes copied.
+pub fn copy_preserving_metadata(src: &Path, dst: &Path) -> Result<u64> {
+    let bytes = fs::copy(src, dst).with_context(|| format!("Failed to copy {} to {}", src.display(), dst.display()))?;
+    let metadata = fs::metadata(src).with_context(|| format!("Failed to read metadata: {}", src.display()))?;
+    let modified = metadata
+        .modified()
+        .with_context(|| format!("Failed to read modification time: {}", src.display()))?;
+    set_modified_time(dst, modified)?;
+    fs::set_permissions(dst, metadata.permissions())
+        .with_context(|| format!("Failed to set permissions: {}", dst.display()))?;
+    Ok(bytes)
+}
+
+/// Set the modification time of a file or directory.
+///
+/// Does not open the file for writing, so this also works for read-only files.
+pub fn set_modified_time(path: &Path, time: SystemTime) -> Result<()> {
+    filetime::set_file_mtime(path, filetime::FileTime::from_system_time(time))
+        .with_context(|| format!("Failed to set modification time: {}", path.display()))
+}
+
+/// Move a file to the given destination.
+///
+/// Tries a plain rename first, and if the destination is on a different drive,
+/// falls back to copying the file with its metadata and removing the original.
+/// Fails if the destination already exists and `overwrite` is false.
+pub fn move_file(src: &Path, dst: &Path, overwrite: bool) -> Result<()> {
+    if !overwrite && dst.exists() {
+        anyhow::bail!("Destination already exists: {}", dst.display());
+    }
+    match fs::rename(src, dst) {
+        Err(error) if error.kind() == io::ErrorKind::CrossesDevices => copy_and_remove_file(src, dst),
+        result => result.with_context(|| format!("Failed to move {} to {}", src.display(), dst.display())),
+    }
+}
+
+/// Copy a file with its metadata and remove the original after a successful copy.
+fn copy_and_remove_file(src: &Path, dst: &Path) -> Result<()> {
+    copy_preserving_metadata(src, dst)?;
+    fs::remove_file(src).with_context(|| format!("Failed to remove file: {}", src.display()))
+}
+
+/// File system types that are mounted over the network.
+const NETWORK_FILESYSTEMS: [&str; 10] = [
+    "9p",
+    "afpfs",
+    "cifs",
+    "fuse.sshfs",
+    "ncpfs",
+    "nfs",
+    "nfs4",
+    "smb3",
+    "smbfs",
+    "webdav",
+];
+
+/// Check if the path is on a network drive.
+///
+/// Detects UNC paths on Windows, and network file system mounts on Linux and macOS.
+#[must_use]
+pub fn is_network_path(path: &Path) -> bool {
+    let path = dunce::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    if path.to_string_lossy().starts_with(r"\\") {
+        return true;
+    }
+    is_network_mount(&path, &read_mounts())
+}
+
+/// Check if the path is under a network mount, using the most specific mount point containing the path.
+fn is_network_mount(path: &Path, mounts: &[(PathBuf, String)]) -> bool {
+    mounts
+        .iter()
+        .filter(|(mount_point, _)| path.starts_with(mount_point))
+        .max_by_key(|(mount_point, _)| mount_point.components().count())
+        .is_some_and(|(_, fs_type)| NETWORK_FILESYSTEMS.contains(&fs_type.as_str()))
+}
+
+/// Read mount points and file system types.
+#[cfg(target_os = "linux")]
+fn read_mounts() -> Vec<(PathBuf, String)> {
+    fs::read_to_string("/proc/mounts")
+        .map(|content| {
+            content
+                .lines()
+                .filter_map(|line| {
+                    let mut parts = line.split_whitespace();
+                    let mount_point = parts.nth(1)?.replace("\\040", " ");
+                    let fs_type = parts.next()?;
+                    Some((PathBuf::from(mount_point), fs_type.to_string()))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Read mount points and file system types.
+#[cfg(target_os = "macos")]
+fn read_mounts() -> Vec<(PathBuf, String)> {
+    // Lines look like: "//user@server/share on /Volumes/share (smbfs, nodev, nosuid, mounted by user)"
+    std::process::Command::new("mount")
+        .output()
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter_map(|line| {
+                    let (_, rest) = line.split_once(" on ")?;
+                    let (mount_point, options) = rest.rsplit_once(" (")?;
+                    let fs_type = options.split([',', ')']).next()?;
+                    Some((PathBuf::from(mount_point), fs_type.trim().to_string()))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Read mount points and file system types.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+const fn read_mounts() -> Vec<(PathBuf, String)> {
+    Vec::new()
+}
+
+/// Check if the IO error is likely temporary, for example from an overloaded network drive.
+#[must_use]
+pub fn is_transient_error(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::PermissionDenied
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::ResourceBusy
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::Interrupted
+    )
+}
+
+/// Longest delay between retries of a transient IO error.
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Run the IO operation and retry transient errors up to the given number of times.
+///
+/// The delay doubles after each attempt up to [`MAX_RETRY_DELAY`].
+/// `on_retry` is called before each retry with the attempt number, error, and delay.
+pub fn retry_transient<T>(
+    retries: u32,
+    delay: Duration,
+    mut operation: impl FnMut() -> io::Result<T>,
+    mut on_retry: impl FnMut(u32, &io::Error, Duration),
+) -> io::Result<T> {
+    let mut attempt = 0;
+    let mut delay = delay;
+    loop {
+        match operation() {
+            Err(error) if attempt < retries && is_transient_error(&error) => {
+                attempt += 1;
+                on_retry(attempt, &error, delay);
+                thread::sleep(delay);
+                delay = delay.saturating_mul(2).min(MAX_RETRY_DELAY);
+            }
+            result => return result,
+        }
+    }
+}
+
 /// Gets the relative path or filename from a full path based on a root directory.
 ///
 /// If the full path is within the root directory, the function returns the relative path.
@@ -135,16 +443,17 @@ pub fn resolve_output_path(path: Option<&str>, absolute_input_path: &Path) -> Re
 /// ```
 #[must_use]
 pub fn get_relative_path_or_filename(full_path: &Path, root: &Path) -> String {
+    let file_name_or_path = || {
+        full_path.file_name().map_or_else(
+            || full_path.display().to_string(),
+            |name| name.to_string_lossy().to_string(),
+        )
+    };
     if full_path == root {
-        return full_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        return file_name_or_path();
     }
     full_path.strip_prefix(root).map_or_else(
-        |_| {
-            full_path.file_name().map_or_else(
-                || full_path.display().to_string(),
-                |name| name.to_string_lossy().to_string(),
-            )
-        },
+        |_| file_name_or_path(),
         |relative_path| relative_path.display().to_string(),
     )
 }
@@ -181,6 +490,214 @@ pub fn path_to_string_relative(path: &Path) -> String {
     path_to_string(&get_relative_path_from_current_working_directory(path))
 }
 
+/// Compare strings in natural sort order.
+///
+/// Digit runs are compared by their numeric value, so "E2" sorts before "E10",
+/// and other text is compared case-insensitively.
+///
+/// ```rust
+/// use std::cmp::Ordering;
+/// use cli_tools::natural_sort_cmp;
+///
+/// assert_eq!(natural_sort_cmp("Show.E2", "Show.E10"), Ordering::Less);
+/// assert_eq!(natural_sort_cmp("b", "A"), Ordering::Greater);
+/// ```
+#[must_use]
+pub fn natural_sort_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => break,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x_digits = take_digits(&mut a_chars);
+                let y_digits = take_digits(&mut b_chars);
+                let x_number = x_digits.trim_start_matches('0');
+                let y_number = y_digits.trim_start_matches('0');
+                let ordering = x_number.len().cmp(&y_number.len()).then_with(|| x_number.cmp(y_number));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+    // Use plain comparison as a tie-breaker for a deterministic order
+    a.cmp(b)
+}
+
+/// Consume consecutive ASCII digits from the iterator.
+fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        digits.push(c);
+    }
+    digits
+}
+
+/// Parse a human-readable file size like "500", "10k", "1.5M", or "2GiB" to bytes.
+///
+/// Units are case-insensitive powers of 1024 and can end with an optional "B" or "iB".
+///
+/// ```rust
+/// use cli_tools::parse_size;
+///
+/// assert_eq!(parse_size("512").unwrap(), 512);
+/// assert_eq!(parse_size("1.5k").unwrap(), 1536);
+/// assert_eq!(parse_size("2 MB").unwrap(), 2 * 1024 * 1024);
+/// assert!(parse_size("big").is_err());
+/// ```
+pub fn parse_size(size: &str) -> Result<u64> {
+    let trimmed = size.trim();
+    let split = trimmed
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    let unit = unit.trim().to_lowercase();
+    let unit = unit
+        .strip_suffix("ib")
+        .or_else(|| unit.strip_suffix('b'))
+        .unwrap_or(&unit);
+    let exponent = match unit {
+        "" => 0,
+        "k" => 1,
+        "m" => 2,
+        "g" => 3,
+        "t" => 4,
+        _ => anyhow::bail!("Unknown size unit in '{size}'"),
+    };
+    let value: f64 = number.parse().with_context(|| format!("Invalid size: '{size}'"))?;
+    let bytes = value * 1024_f64.powi(exponent);
+    if !bytes.is_finite() || bytes >= u64::MAX as f64 {
+        anyhow::bail!("Size is too large: '{size}'");
+    }
+    Ok(bytes.round() as u64)
+}
+
+/// Parse a human-readable duration like "500ms", "30s", "5m", or "1h".
+///
+/// A number without a unit is in seconds.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use cli_tools::parse_duration;
+///
+/// assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+/// assert_eq!(parse_duration("1.5m").unwrap(), Duration::from_secs(90));
+/// assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+/// ```
+pub fn parse_duration(duration: &str) -> Result<Duration> {
+    let trimmed = duration.trim();
+    let split = trimmed
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    let multiplier = match unit.trim().to_lowercase().as_str() {
+        "ms" => 0.001,
+        "" | "s" => 1.0,
+        "m" | "min" => 60.0,
+        "h" => 3600.0,
+        _ => anyhow::bail!("Unknown duration unit in '{duration}'"),
+    };
+    let value: f64 = number
+        .parse()
+        .with_context(|| format!("Invalid duration: '{duration}'"))?;
+    Duration::try_from_secs_f64(value * multiplier).with_context(|| format!("Invalid duration: '{duration}'"))
+}
+
+/// Terminal width used when the output is not a terminal.
+pub const DEFAULT_TERMINAL_WIDTH: usize = 120;
+
+/// Ellipsis used to mark the removed part of truncated text.
+const ELLIPSIS: char = '…';
+
+/// Get the terminal width in columns.
+///
+/// Falls back to the `COLUMNS` environment variable,
+/// and then to [`DEFAULT_TERMINAL_WIDTH`] when the output is not a terminal.
+#[must_use]
+pub fn terminal_width() -> usize {
+    if let Some((terminal_size::Width(width), _)) = terminal_size::terminal_size() {
+        return usize::from(width);
+    }
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.trim().parse::<usize>().ok())
+        .filter(|&columns| columns > 0)
+        .unwrap_or(DEFAULT_TERMINAL_WIDTH)
+}
+
+/// Shorten a path to fit the given display width by replacing the middle with an ellipsis.
+///
+/// The file name is kept intact when it fits,
+/// otherwise the middle of the whole path is replaced.
+/// Width is measured in terminal columns, so wide characters like CJK count as two.
+///
+/// ```rust
+/// use cli_tools::truncate_path_middle;
+///
+/// assert_eq!(truncate_path_middle("/Volumes/Share/Movies/Some.Movie.mkv", 24), "/Volumes…/Some.Movie.mkv");
+/// assert_eq!(truncate_path_middle("short/path.txt", 24), "short/path.txt");
+/// ```
+#[must_use]
+pub fn truncate_path_middle(path: &str, max_width: usize) -> String {
+    if path.width() <= max_width {
+        return path.to_string();
+    }
+    if let Some(index) = path.rfind(['/', '\\']) {
+        let (directory, file_name) = path.split_at(index);
+        let file_name_width = file_name.width();
+        if file_name_width < max_width {
+            let head = take_width(directory.chars(), max_width - file_name_width - 1);
+            return format!("{head}{ELLIPSIS}{file_name}");
+        }
+    }
+    truncate_middle(path, max_width)
+}
+
+/// Shorten text to fit the given display width by replacing the middle with an ellipsis.
+/// Keeps slightly more from the end since it usually has the most specific part.
+fn truncate_middle(text: &str, max_width: usize) -> String {
+    if text.width() <= max_width {
+        return text.to_string();
+    }
+    if max_width == 0 {
+        return String::new();
+    }
+    let available = max_width - 1;
+    let head_width = available / 2;
+    let tail_width = available - head_width;
+    let head = take_width(text.chars(), head_width);
+    let tail: String = take_width(text.chars().rev(), tail_width).chars().rev().collect();
+    format!("{head}{ELLIPSIS}{tail}")
+}
+
+/// Collect characters until the display width would exceed the limit.
+fn take_width(chars: impl Iterator<Item = char>, max_width: usize) -> String {
+    let mut width = 0;
+    chars
+        .take_while(|c| {
+            width += c.width().unwrap_or(0);
+            width <= max_width
+        })
+        .collect()
+}
+
+/// Print a stacked diff of two paths, truncated to fit the terminal width.
+pub fn show_path_diff(old: &str, new: &str) {
+    let width = terminal_width();
+    show_diff(&truncate_path_middle(old, width), &truncate_path_middle(new, width));
+}
+
 /// Print a stacked diff of the changes.
 pub fn show_diff(old: &str, new: &str) {
     let changeset = Changeset::new(old, new, "");
@@ -195,16 +712,16 @@ pub fn show_diff(old: &str, new: &str) {
             }
             Difference::Add(ref x) => {
                 if x.chars().all(char::is_whitespace) {
-                    new_diff.push_str(&x.to_string().on_green().to_string());
+                    new_diff.push_str(&x.clone().on_green().to_string());
                 } else {
-                    new_diff.push_str(&x.to_string().green().to_string());
+                    new_diff.push_str(&x.clone().green().to_string());
                 }
             }
             Difference::Rem(ref x) => {
                 if x.chars().all(char::is_whitespace) {
-                    old_diff.push_str(&x.to_string().on_red().to_string());
+                    old_diff.push_str(&x.clone().on_red().to_string());
                 } else {
-                    old_diff.push_str(&x.to_string().red().to_string());
+                    old_diff.push_str(&x.clone().red().to_string());
                 }
             }
         }
@@ -259,6 +776,94 @@ mod lib_tests {
         assert!(!is_hidden(&entry));
     }
 
+    fn find_entry(root: &Path, name: &str) -> DirEntry {
+        WalkDir::new(root)
+            .into_iter()
+            .filter_map(Result::ok)
+            .find(|e| e.file_name().to_string_lossy().eq(name))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_should_skip_entry_default() {
+        let dir = tempfile::Builder::new().prefix("skip").tempdir().unwrap();
+        std::fs::create_dir(dir.path().join(".git")).unwrap();
+        std::fs::create_dir(dir.path().join("$RECYCLE.BIN")).unwrap();
+        std::fs::create_dir(dir.path().join("node_modules")).unwrap();
+        File::create(dir.path().join("file.txt")).unwrap();
+
+        assert!(should_skip_entry(&find_entry(dir.path(), ".git")));
+        assert!(should_skip_entry(&find_entry(dir.path(), "$RECYCLE.BIN")));
+        assert!(!should_skip_entry(&find_entry(dir.path(), "node_modules")));
+        assert!(!should_skip_entry(&find_entry(dir.path(), "file.txt")));
+    }
+
+    #[test]
+    fn test_skip_rules_disabled() {
+        let dir = tempfile::Builder::new().prefix("skip").tempdir().unwrap();
+        std::fs::create_dir(dir.path().join(".git")).unwrap();
+        std::fs::create_dir(dir.path().join("$RECYCLE.BIN")).unwrap();
+
+        let rules = SkipRules {
+            skip_hidden: false,
+            skip_system_dirs: false,
+            ..SkipRules::default()
+        };
+        assert!(!rules.should_skip(&find_entry(dir.path(), ".git")));
+        assert!(!rules.should_skip(&find_entry(dir.path(), "$RECYCLE.BIN")));
+    }
+
+    #[test]
+    fn test_skip_rules_system_dirs_only_match_directories() {
+        let dir = tempfile::Builder::new().prefix("skip").tempdir().unwrap();
+        File::create(dir.path().join("lost+found")).unwrap();
+
+        let rules = SkipRules::default();
+        assert!(!rules.should_skip(&find_entry(dir.path(), "lost+found")));
+    }
+
+    #[test]
+    fn test_skip_rules_names_and_extensions() {
+        let dir = tempfile::Builder::new().prefix("skip").tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("node_modules")).unwrap();
+        std::fs::create_dir(dir.path().join("backup.bak")).unwrap();
+        File::create(dir.path().join("notes.TXT")).unwrap();
+        File::create(dir.path().join("video.mp4")).unwrap();
+
+        let rules = SkipRules {
+            skip_names: vec!["node_modules".to_string()],
+            skip_extensions: vec!["txt".to_string(), ".bak".to_string()],
+            ..SkipRules::default()
+        };
+        assert!(rules.should_skip(&find_entry(dir.path(), "node_modules")));
+        assert!(rules.should_skip(&find_entry(dir.path(), "notes.TXT")));
+        assert!(!rules.should_skip(&find_entry(dir.path(), "video.mp4")));
+        // Extensions only apply to files
+        assert!(!rules.should_skip(&find_entry(dir.path(), "backup.bak")));
+    }
+
+    #[test]
+    fn test_is_system_directory() {
+        assert!(is_system_directory("$RECYCLE.BIN"));
+        assert!(is_system_directory("$Recycle.Bin"));
+        assert!(is_system_directory("System Volume Information"));
+        assert!(!is_system_directory("Documents"));
+    }
+
+    #[test]
+    fn test_natural_sort_cmp() {
+        assert_eq!(natural_sort_cmp("E2", "E10"), Ordering::Less);
+        assert_eq!(natural_sort_cmp("E10", "E9"), Ordering::Greater);
+        assert_eq!(natural_sort_cmp("E09", "E10"), Ordering::Less);
+        assert_eq!(natural_sort_cmp("show.s01e02", "Show.S01E03"), Ordering::Less);
+        assert_eq!(natural_sort_cmp("file", "file2"), Ordering::Less);
+        assert_eq!(natural_sort_cmp("same", "same"), Ordering::Equal);
+
+        let mut names = vec!["E10", "E1", "e3", "E02"];
+        names.sort_by(|a, b| natural_sort_cmp(a, b));
+        assert_eq!(names, vec!["E1", "E02", "e3", "E10"]);
+    }
+
     #[test]
     fn test_resolve_input_path_valid() {
         let dir = tempdir().unwrap();
@@ -308,4 +913,426 @@ mod lib_tests {
         assert!(output_path.is_ok());
         assert_eq!(output_path.unwrap(), dunce::simplified(dir.path()));
     }
+
+    #[test]
+    fn test_copy_preserving_metadata() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("source.txt");
+        let dst = dir.path().join("copy.txt");
+        fs::write(&src, "content").unwrap();
+
+        let modified = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000);
+        File::options()
+            .write(true)
+            .open(&src)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&src, fs::Permissions::from_mode(0o640)).unwrap();
+        }
+
+        let bytes = copy_preserving_metadata(&src, &dst).unwrap();
+        assert_eq!(bytes, 7);
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "content");
+        assert_eq!(fs::metadata(&dst).unwrap().modified().unwrap(), modified);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&dst).unwrap().permissions().mode() & 0o777, 0o640);
+        }
+    }
+
+    #[test]
+    fn test_move_file() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("source.txt");
+        let dst = dir.path().join("target.txt");
+        fs::write(&src, "source").unwrap();
+        fs::write(&dst, "target").unwrap();
+
+        assert!(move_file(&src, &dst, false).is_err());
+        assert!(src.exists());
+
+        move_file(&src, &dst, true).unwrap();
+        assert!(!src.exists());
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "source");
+    }
+
+    #[test]
+    fn test_move_file_missing_source_does_not_copy() {
+        let dir = tempdir().unwrap();
+        let dst = dir.path().join("target.txt");
+        assert!(move_file(&dir.path().join("missing.txt"), &dst, false).is_err());
+        assert!(!dst.exists());
+    }
+
+    #[test]
+    fn test_copy_and_remove_read_only_file() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("source.txt");
+        let dst = dir.path().join("target.txt");
+        fs::write(&src, "content").unwrap();
+        let modified = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000);
+        set_modified_time(&src, modified).unwrap();
+        let mut permissions = fs::metadata(&src).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&src, permissions).unwrap();
+
+        copy_and_remove_file(&src, &dst).unwrap();
+        assert!(!src.exists());
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "content");
+        let metadata = fs::metadata(&dst).unwrap();
+        assert_eq!(metadata.modified().unwrap(), modified);
+        assert!(metadata.permissions().readonly());
+    }
+
+    #[test]
+    fn test_exclude_prunes_directories() {
+        let dir = tempfile::Builder::new().prefix("walk").tempdir().unwrap();
+        let excluded = dir.path().join("node_modules");
+        for index in 0..20 {
+            let nested = excluded.join(format!("package{index}"));
+            std::fs::create_dir_all(&nested).unwrap();
+            File::create(nested.join("index.js")).unwrap();
+        }
+        File::create(dir.path().join("node_modules.txt")).unwrap();
+        File::create(dir.path().join("main.js")).unwrap();
+
+        let count_visited = |rules: &SkipRules| {
+            let mut visited = 0;
+            let entries: Vec<_> = WalkDir::new(dir.path())
+                .into_iter()
+                .filter_entry(|entry| {
+                    visited += 1;
+                    !rules.should_skip(entry)
+                })
+                .filter_map(Result::ok)
+                .collect();
+            (visited, entries.len())
+        };
+
+        // Root, node_modules, and the two files
+        let rules = SkipRules {
+            exclude_dirs: vec!["node_modules".to_string()],
+            ..SkipRules::default()
+        };
+        assert_eq!(count_visited(&rules), (4, 3));
+
+        let rules = SkipRules {
+            exclude: vec!["node_modules".to_string()],
+            ..SkipRules::default()
+        };
+        assert_eq!(count_visited(&rules), (4, 2));
+    }
+
+    #[test]
+    fn test_is_network_mount() {
+        let mounts = vec![
+            (PathBuf::from("/"), "ext4".to_string()),
+            (PathBuf::from("/mnt/share"), "cifs".to_string()),
+            (PathBuf::from("/mnt/share/local"), "ext4".to_string()),
+            (PathBuf::from("/Volumes/nas"), "smbfs".to_string()),
+        ];
+        assert!(is_network_mount(Path::new("/mnt/share/videos/file.mp4"), &mounts));
+        assert!(is_network_mount(Path::new("/Volumes/nas"), &mounts));
+        assert!(!is_network_mount(Path::new("/mnt/share/local/file.mp4"), &mounts));
+        assert!(!is_network_mount(Path::new("/mnt/shared"), &mounts));
+        assert!(!is_network_mount(Path::new("/home/user"), &mounts));
+        assert!(!is_network_mount(Path::new("/home/user"), &[]));
+    }
+
+    #[test]
+    fn test_retry_transient() {
+        let mut attempts = 0;
+        let mut retries = Vec::new();
+        let result = retry_transient(
+            3,
+            Duration::from_millis(1),
+            || {
+                attempts += 1;
+                if attempts < 3 {
+                    Err(io::Error::from(io::ErrorKind::PermissionDenied))
+                } else {
+                    Ok(attempts)
+                }
+            },
+            |attempt, _, delay| retries.push((attempt, delay)),
+        );
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(
+            retries,
+            vec![(1, Duration::from_millis(1)), (2, Duration::from_millis(2))]
+        );
+    }
+
+    #[test]
+    fn test_retry_transient_gives_up() {
+        let mut attempts = 0;
+        let result: io::Result<()> = retry_transient(
+            2,
+            Duration::ZERO,
+            || {
+                attempts += 1;
+                Err(io::Error::from(io::ErrorKind::TimedOut))
+            },
+            |_, _, _| {},
+        );
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+
+        let mut attempts = 0;
+        let result: io::Result<()> = retry_transient(
+            2,
+            Duration::ZERO,
+            || {
+                attempts += 1;
+                Err(io::Error::from(io::ErrorKind::NotFound))
+            },
+            |_, _, _| {},
+        );
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn test_resolve_input_paths() {
+        let dir = tempdir().unwrap();
+        let first = dir.path().join("first");
+        let second = dir.path().join("second");
+        fs::create_dir(&first).unwrap();
+        fs::create_dir(&second).unwrap();
+        let paths = [&first, &second, &first].map(|path| path.to_string_lossy().to_string());
+        let resolved = resolve_input_paths(&paths).unwrap();
+        assert_eq!(
+            resolved,
+            vec![
+                dunce::canonicalize(&first).unwrap(),
+                dunce::canonicalize(&second).unwrap()
+            ]
+        );
+        assert_eq!(resolve_input_paths(&[]).unwrap().len(), 1);
+        assert!(resolve_input_paths(&[dir.path().join("missing").to_string_lossy().to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("0").unwrap(), 0);
+        assert_eq!(parse_size("100").unwrap(), 100);
+        assert_eq!(parse_size("10k").unwrap(), 10 * 1024);
+        assert_eq!(parse_size("10KB").unwrap(), 10 * 1024);
+        assert_eq!(parse_size("10KiB").unwrap(), 10 * 1024);
+        assert_eq!(parse_size("1.5M").unwrap(), 1024 * 1024 * 3 / 2);
+        assert_eq!(parse_size(" 2 gb ").unwrap(), 2 * 1024 * 1024 * 1024);
+        assert_eq!(parse_size("1T").unwrap(), 1024_u64.pow(4));
+        assert_eq!(parse_size("3b").unwrap(), 3);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("0").unwrap(), Duration::ZERO);
+        assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("100ms").unwrap(), Duration::from_millis(100));
+        assert_eq!(parse_duration("2m").unwrap(), Duration::from_mins(2));
+        assert_eq!(parse_duration("0.5h").unwrap(), Duration::from_mins(30));
+        assert_eq!(parse_duration(" 1 min ").unwrap(), Duration::from_mins(1));
+        for duration in ["", "s", "abc", "10d", "-1s", "1.2.3s"] {
+            assert!(parse_duration(duration).is_err(), "{duration}");
+        }
+    }
+
+    #[test]
+    fn test_parse_size_invalid() {
+        for size in ["", "k", "abc", "10x", "1.2.3M", "-1", "99999999999999999999T"] {
+            assert!(parse_size(size).is_err(), "{size}");
+        }
+    }
+
+    #[test]
+    fn test_truncate_path_middle() {
+        let path = "/Volumes/Share/Movies/Some.Movie.mkv";
+        assert_eq!(truncate_path_middle(path, 100), path);
+        assert_eq!(truncate_path_middle(path, path.len()), path);
+        assert_eq!(truncate_path_middle(path, 24), "/Volumes…/Some.Movie.mkv");
+        assert_eq!(truncate_path_middle(path, 16), "…/Some.Movie.mkv");
+        assert_eq!(
+            truncate_path_middle(r"C:\Users\Name\Videos\clip.mp4", 16),
+            r"C:\Use…\clip.mp4"
+        );
+        for width in 0..=path.len() {
+            assert!(truncate_path_middle(path, width).width() <= width, "{width}");
+        }
+    }
+
+    #[test]
+    fn test_truncate_path_middle_small_width() {
+        let path = "/some/dir/long_file_name.txt";
+        assert_eq!(truncate_path_middle(path, 10), "/som…e.txt");
+        assert_eq!(truncate_path_middle(path, 1), "…");
+        assert_eq!(truncate_path_middle(path, 0), "");
+        assert_eq!(truncate_path_middle("no_separator_name", 9), "no_s…name");
+    }
+
+    #[test]
+    fn test_truncate_path_middle_wide_characters() {
+        let path = "/音楽/アーティスト/アルバム/曲名.flac";
+        assert_eq!(path.width(), 37);
+        assert_eq!(truncate_path_middle(path, 37), path);
+        // Wide characters are not split even if it leaves a column unused
+        assert_eq!(truncate_path_middle(path, 20), "/音楽/ア…/曲名.flac");
+        assert_eq!(truncate_path_middle(path, 18), "/音楽/…/曲名.flac");
+        for width in 0..=path.width() {
+            assert!(truncate_path_middle(path, width).width() <= width, "{width}");
+        }
+    }
+
+    #[test]
+    fn test_terminal_width() {
+        assert!(terminal_width() > 0);
+    }
+}
+
+#[cfg(test)]
+mod path_property_tests {
+    use super::*;
+
+    use proptest::prelude::*;
+
+    /// File stem that starts with a regular character and may contain dots.
+    fn file_stem() -> impl Strategy<Value = String> {
+        "[a-zA-Z0-9][a-zA-Z0-9 ._-]{0,11}"
+    }
+
+    fn extension() -> impl Strategy<Value = String> {
+        "[a-zA-Z0-9]{1,5}"
+    }
+
+    fn directory() -> impl Strategy<Value = PathBuf> {
+        prop::collection::vec("[a-zA-Z0-9 _-]{1,8}", 0..4).prop_map(|parts| parts.iter().collect())
+    }
+
+    fn file_path() -> impl Strategy<Value = PathBuf> {
+        (directory(), file_stem(), prop::option::of(extension())).prop_map(|(dir, stem, extension)| {
+            let file_name = extension.map_or_else(|| stem.clone(), |ext| format!("{stem}.{ext}"));
+            dir.join(file_name)
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn append_extension_sets_requested_extension(
+            path in file_path(),
+            extension in extension(),
+            leading_dot in any::<bool>(),
+        ) {
+            let requested = if leading_dot { format!(".{extension}") } else { extension.clone() };
+            let result = append_extension_to_path(path.clone(), &requested);
+            prop_assert_eq!(result.extension(), Some(OsStr::new(&extension)));
+            prop_assert_eq!(result.parent(), path.parent());
+            prop_assert_eq!(result.file_stem(), path.file_name());
+        }
+
+        #[test]
+        fn insert_suffix_keeps_parent_and_extension(path in file_path(), suffix in "[ _-][a-zA-Z0-9]{1,6}") {
+            let result = insert_suffix_before_extension(&path, &suffix);
+            prop_assert_eq!(result.parent(), path.parent());
+            prop_assert_eq!(result.extension(), path.extension());
+            let expected_stem = format!("{}{suffix}", path.file_stem().unwrap().to_string_lossy());
+            prop_assert_eq!(result.file_stem(), Some(OsStr::new(&expected_stem)));
+        }
+
+        #[test]
+        fn relative_path_round_trips(root in directory(), relative in file_path()) {
+            let root = Path::new("/").join(root);
+            let full_path = root.join(&relative);
+            let result = get_relative_path_or_filename(&full_path, &root);
+            prop_assert_eq!(&result, &relative.display().to_string());
+            prop_assert_eq!(root.join(&result), full_path);
+        }
+
+        #[test]
+        fn relative_path_outside_root_is_file_name(root in directory(), path in file_path()) {
+            let root = Path::new("/root").join(root);
+            let full_path = Path::new("/other").join(&path);
+            let result = get_relative_path_or_filename(&full_path, &root);
+            prop_assert_eq!(Some(OsStr::new(&result)), path.file_name());
+        }
+
+        #[test]
+        fn output_path_is_used_as_given(
+            path in file_path().prop_filter("surrounding whitespace is trimmed", |path| {
+                let path = path.to_string_lossy();
+                path.trim() == path
+            }),
+            padding in "[ \t]{0,2}",
+        ) {
+            let given = format!("{padding}{}{padding}", path.display());
+            let result = resolve_output_path(Some(&given), Path::new("/input")).unwrap();
+            prop_assert_eq!(result, dunce::simplified(&path).to_path_buf());
+        }
+
+        #[test]
+        fn blank_output_path_defaults_to_input_directory(blank in "[ \t]{0,3}", file_name in file_stem()) {
+            let dir = tempfile::tempdir().unwrap();
+            let file = dir.path().join(&file_name);
+            fs::write(&file, "").unwrap();
+            prop_assert_eq!(resolve_output_path(Some(&blank), dir.path()).unwrap(), dir.path());
+            prop_assert_eq!(resolve_output_path(Some(&blank), &file).unwrap(), dir.path());
+            prop_assert_eq!(resolve_output_path(None, &file).unwrap(), dir.path());
+        }
+    }
+
+    #[cfg(unix)]
+    proptest! {
+        #[test]
+        fn insert_suffix_handles_invalid_unicode(
+            stem in prop::collection::vec(any::<u8>().prop_filter("separator", |byte| ![b'/', b'.', 0].contains(byte)), 1..12),
+            extension in extension(),
+        ) {
+            use std::os::unix::ffi::OsStrExt;
+
+            let mut file_name = stem.clone();
+            file_name.extend_from_slice(format!(".{extension}").as_bytes());
+            let path = Path::new("/videos").join(OsStr::from_bytes(&file_name));
+            let result = insert_suffix_before_extension(&path, ".x265");
+            let expected = format!("{}.x265.{extension}", os_str_to_string(OsStr::from_bytes(&stem)));
+            prop_assert_eq!(result, Path::new("/videos").join(expected));
+        }
+    }
+
+    #[test]
+    fn test_path_edge_cases() {
+        assert_eq!(
+            insert_suffix_before_extension(Path::new("dir/.hidden"), "_1"),
+            Path::new("dir/.hidden_1")
+        );
+        assert_eq!(
+            insert_suffix_before_extension(Path::new("name."), "_1"),
+            Path::new("name_1.")
+        );
+        assert_eq!(
+            append_extension_to_path(PathBuf::from("file.txt"), ".nfd"),
+            Path::new("file.txt.nfd")
+        );
+        assert_eq!(get_relative_path_or_filename(Path::new("/"), Path::new("/")), "/");
+        assert_eq!(
+            get_relative_path_or_filename(Path::new("/dir/../file.txt"), Path::new("/dir")),
+            "../file.txt"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_insert_suffix_invalid_unicode_stem() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(OsStr::from_bytes(b"caf\xe9 video.mp4"));
+        assert_eq!(
+            insert_suffix_before_extension(path, ".x265"),
+            Path::new("caf video.x265.mp4")
+        );
+    }
 }
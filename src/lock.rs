@@ -0,0 +1,361 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Name of the lock file that tools create in a directory while processing it.
+pub const LOCK_FILE_NAME: &str = ".cli-tools.lock";
+
+/// A lock older than this is considered stale if the process that created it is no longer running.
+const STALE_LOCK_AGE: Duration = Duration::from_mins(10);
+
+/// How often to check the lock again while waiting for it.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often a held lock gets a new timestamp so long runs are not judged stale.
+const LOCK_REFRESH_INTERVAL: Duration = Duration::from_mins(1);
+
+/// Contents of a lock file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockInfo {
+    /// Name of the tool holding the lock
+    pub tool: String,
+    /// Process id of the tool holding the lock
+    pub pid: u32,
+    /// Time the lock was created in seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+/// Cooperative lock for a directory that is released when dropped.
+#[derive(Debug)]
+pub struct DirLock {
+    path: PathBuf,
+    /// Contents of the lock file written by this lock
+    info: LockInfo,
+    refreshed: Instant,
+}
+
+/// Acquire the lock for a directory so other tools do not process it at the same time.
+///
+/// If another process holds the lock, waits up to `wait` for it to be released.
+/// Returns `None` if the lock is still held after waiting.
+/// Stale locks left behind by processes that are no longer running are removed.
+/// On platforms where it can not be checked if the process is running, locks are never considered stale.
+pub fn acquire_dir_lock(dir: &Path, tool: &str, wait: Duration) -> Result<Option<DirLock>> {
+    let path = dir.join(LOCK_FILE_NAME);
+    let info = LockInfo {
+        tool: tool.to_string(),
+        pid: std::process::id(),
+        timestamp: unix_timestamp(SystemTime::now()),
+    };
+    let content = serde_json::to_string(&info).context("Failed to serialize lock info")?;
+    let deadline = Instant::now() + wait;
+    loop {
+        match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                let lock = DirLock {
+                    path,
+                    info,
+                    refreshed: Instant::now(),
+                };
+                file.write_all(content.as_bytes())
+                    .with_context(|| format!("Failed to write lock file: {}", lock.path.display()))?;
+                return Ok(Some(lock));
+            }
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+                if remove_stale_lock(&path) {
+                    continue;
+                }
+            }
+            Err(error) => {
+                return Err(error).with_context(|| format!("Failed to create lock file: {}", path.display()));
+            }
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(LOCK_POLL_INTERVAL.min(deadline.saturating_duration_since(Instant::now())));
+    }
+}
+
+/// Read the lock info for a directory if it is locked.
+#[must_use]
+pub fn read_dir_lock(dir: &Path) -> Option<LockInfo> {
+    read_lock_info(&dir.join(LOCK_FILE_NAME))
+}
+
+impl DirLock {
+    /// Path of the lock file.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Directory that is locked.
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        self.path.parent().unwrap_or(&self.path)
+    }
+
+    /// Update the lock location after the locked directory itself was renamed.
+    pub fn moved_to(&mut self, dir: &Path) {
+        self.path = dir.join(LOCK_FILE_NAME);
+    }
+
+    /// Refresh the lock timestamp so a long run is not judged stale by other processes.
+    ///
+    /// Only writes the lock file once per refresh interval, so this can be called often.
+    /// Failing to write is ignored since the lock is still held.
+    pub fn keep_alive(&mut self) {
+        if self.refreshed.elapsed() < LOCK_REFRESH_INTERVAL || !self.is_owned() {
+            return;
+        }
+        let info = LockInfo {
+            timestamp: unix_timestamp(SystemTime::now()),
+            ..self.info.clone()
+        };
+        if let Ok(content) = serde_json::to_string(&info) {
+            if fs::write(&self.path, content).is_ok() {
+                self.info = info;
+            }
+        }
+        self.refreshed = Instant::now();
+    }
+
+    /// Check that the lock file still belongs to this lock.
+    fn is_owned(&self) -> bool {
+        read_lock_info(&self.path).is_some_and(|info| info == self.info)
+    }
+}
+
+impl Drop for DirLock {
+    fn drop(&mut self) {
+        // Do not remove a lock that another process took over
+        if self.is_owned() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+fn read_lock_info(path: &Path) -> Option<LockInfo> {
+    let content = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Remove a stale lock file. Returns false if the lock is still held.
+///
+/// The lock file is first moved to a unique name and checked again there,
+/// so a fresh lock created by another process in the meantime is never deleted.
+fn remove_stale_lock(path: &Path) -> bool {
+    if !is_stale_lock(path) {
+        return false;
+    }
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.subsec_nanos());
+    let moved = path.with_file_name(format!("{LOCK_FILE_NAME}.{}.{nanos}", std::process::id()));
+    if fs::rename(path, &moved).is_err() {
+        // Another process removed or replaced it first
+        return false;
+    }
+    if is_stale_lock(&moved) {
+        let _ = fs::remove_file(&moved);
+        return true;
+    }
+    // Got a fresh lock instead: put it back unless yet another lock was created
+    let _ = fs::hard_link(&moved, path);
+    let _ = fs::remove_file(&moved);
+    false
+}
+
+/// Check if the lock is old and the process holding it is not running anymore.
+/// A lock file that can not be parsed is judged by its modification time only.
+fn is_stale_lock(path: &Path) -> bool {
+    let (timestamp, running) = if let Some(info) = read_lock_info(path) {
+        // Assume the process is running when it can not be checked
+        (info.timestamp, is_process_running(info.pid).unwrap_or(true))
+    } else {
+        let Ok(modified) = fs::metadata(path).and_then(|metadata| metadata.modified()) else {
+            return false;
+        };
+        (unix_timestamp(modified), false)
+    };
+    let age = unix_timestamp(SystemTime::now()).saturating_sub(timestamp);
+    age >= STALE_LOCK_AGE.as_secs() && !running
+}
+
+/// Check if a process with the given id exists.
+/// Returns `None` when it can not be determined on this platform.
+fn is_process_running(pid: u32) -> Option<bool> {
+    if cfg!(target_os = "linux") {
+        Some(Path::new("/proc").join(pid.to_string()).exists())
+    } else if cfg!(unix) {
+        Command::new("ps")
+            .args(["-p", &pid.to_string()])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .ok()
+            .map(|status| status.success())
+    } else {
+        None
+    }
+}
+
+fn unix_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs())
+}
+
+#[cfg(test)]
+mod lock_tests {
+    use super::*;
+
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_lock_is_released_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = acquire_dir_lock(dir.path(), "test", Duration::ZERO).unwrap().unwrap();
+        assert!(lock.path().is_file());
+        let info = read_dir_lock(dir.path()).unwrap();
+        assert_eq!(info.tool, "test");
+        assert_eq!(info.pid, std::process::id());
+
+        assert!(acquire_dir_lock(dir.path(), "other", Duration::ZERO).unwrap().is_none());
+        drop(lock);
+        assert!(read_dir_lock(dir.path()).is_none());
+        assert!(acquire_dir_lock(dir.path(), "other", Duration::ZERO).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_lock_follows_renamed_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let locked = dir.path().join("Locked");
+        let renamed = dir.path().join("Renamed");
+        fs::create_dir(&locked).unwrap();
+        let mut lock = acquire_dir_lock(&locked, "test", Duration::ZERO).unwrap().unwrap();
+        assert_eq!(lock.dir(), locked);
+        fs::rename(&locked, &renamed).unwrap();
+        lock.moved_to(&renamed);
+        assert_eq!(lock.dir(), renamed);
+        drop(lock);
+        assert!(!renamed.join(LOCK_FILE_NAME).exists());
+    }
+
+    #[test]
+    fn test_lock_contention_between_threads() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        let (locked_sender, locked_receiver) = mpsc::channel();
+        let holder = {
+            let root = root.clone();
+            thread::spawn(move || {
+                let lock = acquire_dir_lock(&root, "holder", Duration::ZERO).unwrap().unwrap();
+                locked_sender.send(()).unwrap();
+                thread::sleep(Duration::from_millis(400));
+                drop(lock);
+            })
+        };
+        locked_receiver.recv().unwrap();
+
+        // Gives up while the other thread still holds the lock
+        assert!(acquire_dir_lock(&root, "waiter", Duration::from_millis(50))
+            .unwrap()
+            .is_none());
+
+        // Gets the lock once the other thread releases it
+        let lock = acquire_dir_lock(&root, "waiter", Duration::from_secs(5)).unwrap();
+        assert_eq!(read_dir_lock(&root).unwrap().tool, "waiter");
+        drop(lock);
+        holder.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_stale_lock_is_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let stale = LockInfo {
+            tool: "crashed".to_string(),
+            pid: u32::MAX,
+            timestamp: unix_timestamp(SystemTime::now()) - STALE_LOCK_AGE.as_secs() - 1,
+        };
+        fs::write(dir.path().join(LOCK_FILE_NAME), serde_json::to_string(&stale).unwrap()).unwrap();
+        let lock = acquire_dir_lock(dir.path(), "test", Duration::ZERO).unwrap();
+        assert!(lock.is_some());
+        assert_eq!(read_dir_lock(dir.path()).unwrap().tool, "test");
+        // The moved stale lock is not left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_fresh_or_running_lock_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock_path = dir.path().join(LOCK_FILE_NAME);
+        let now = unix_timestamp(SystemTime::now());
+
+        let fresh = LockInfo {
+            tool: "other".to_string(),
+            pid: u32::MAX,
+            timestamp: now,
+        };
+        fs::write(&lock_path, serde_json::to_string(&fresh).unwrap()).unwrap();
+        assert!(acquire_dir_lock(dir.path(), "test", Duration::ZERO).unwrap().is_none());
+
+        let running = LockInfo {
+            tool: "other".to_string(),
+            pid: std::process::id(),
+            timestamp: now - STALE_LOCK_AGE.as_secs() - 1,
+        };
+        fs::write(&lock_path, serde_json::to_string(&running).unwrap()).unwrap();
+        assert!(!remove_stale_lock(&lock_path));
+        assert!(acquire_dir_lock(dir.path(), "test", Duration::ZERO).unwrap().is_none());
+        assert_eq!(read_dir_lock(dir.path()), Some(running));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_is_process_running() {
+        assert_eq!(is_process_running(std::process::id()), Some(true));
+        assert_eq!(is_process_running(u32::MAX), Some(false));
+    }
+
+    #[test]
+    fn test_lock_taken_over_is_not_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = acquire_dir_lock(dir.path(), "test", Duration::ZERO).unwrap().unwrap();
+        let other = LockInfo {
+            tool: "other".to_string(),
+            pid: std::process::id(),
+            timestamp: unix_timestamp(SystemTime::now()),
+        };
+        fs::write(lock.path(), serde_json::to_string(&other).unwrap()).unwrap();
+        drop(lock);
+        assert_eq!(read_dir_lock(dir.path()), Some(other));
+    }
+
+    #[test]
+    fn test_keep_alive_refreshes_timestamp() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut lock = acquire_dir_lock(dir.path(), "test", Duration::ZERO).unwrap().unwrap();
+        lock.info.timestamp -= STALE_LOCK_AGE.as_secs();
+        fs::write(lock.path(), serde_json::to_string(&lock.info).unwrap()).unwrap();
+
+        // Not written again before the refresh interval
+        lock.keep_alive();
+        assert_eq!(read_dir_lock(dir.path()).unwrap(), lock.info);
+
+        lock.refreshed = Instant::now().checked_sub(LOCK_REFRESH_INTERVAL).unwrap();
+        lock.keep_alive();
+        let info = read_dir_lock(dir.path()).unwrap();
+        assert!(info.timestamp >= unix_timestamp(SystemTime::now()) - 1);
+        assert_eq!(info, lock.info);
+        drop(lock);
+        assert!(read_dir_lock(dir.path()).is_none());
+    }
+}
@@ -0,0 +1,238 @@
+use std::io::{self, IsTerminal, Write};
+use std::sync::{LazyLock, Mutex, MutexGuard};
+
+use colored::Colorize;
+
+/// Move the cursor to the start of the line and clear it.
+const CLEAR_LINE: &str = "\r\x1b[2K";
+
+static GLOBAL: LazyLock<OutputCoordinator> = LazyLock::new(|| OutputCoordinator::new(io::stdout().is_terminal()));
+
+/// Coordinates in-place progress lines with regular output lines.
+///
+/// Messages printed while a progress line is active first clear the progress line,
+/// then print the message and redraw the progress line below it,
+/// so the two never overwrite each other.
+/// When the output is not a terminal, progress lines are not drawn
+/// and messages are printed as is without escape sequences.
+#[derive(Debug)]
+pub struct OutputCoordinator {
+    interactive: bool,
+    /// Text of the active progress line
+    progress: Mutex<Option<String>>,
+}
+
+/// Handle for the active progress line. The line is cleared when dropped.
+#[derive(Debug)]
+pub struct ProgressLine<'a> {
+    coordinator: &'a OutputCoordinator,
+}
+
+impl OutputCoordinator {
+    /// Create a coordinator. Progress lines are only drawn when `interactive` is true.
+    #[must_use]
+    pub const fn new(interactive: bool) -> Self {
+        Self {
+            interactive,
+            progress: Mutex::new(None),
+        }
+    }
+
+    /// Shared coordinator for stdout.
+    #[must_use]
+    pub fn global() -> &'static Self {
+        &GLOBAL
+    }
+
+    /// Check if progress lines are drawn.
+    #[must_use]
+    pub const fn is_interactive(&self) -> bool {
+        self.interactive
+    }
+
+    /// Check if a progress line is currently registered.
+    #[must_use]
+    pub fn has_progress(&self) -> bool {
+        self.state().is_some()
+    }
+
+    /// Register a progress line that is drawn to stdout.
+    #[must_use]
+    pub fn start_progress(&self) -> ProgressLine<'_> {
+        *self.state() = Some(String::new());
+        ProgressLine { coordinator: self }
+    }
+
+    /// Write a message line to `message_out` without corrupting the progress line in `progress_out`.
+    ///
+    /// # Errors
+    /// Returns an error if writing fails.
+    pub fn write_line(
+        &self,
+        progress_out: &mut impl Write,
+        message_out: &mut impl Write,
+        message: &str,
+    ) -> io::Result<()> {
+        // Hold the lock until done so other threads can not draw in between
+        let state = self.state();
+        let redraw = state.as_deref().filter(|_| self.interactive);
+        if redraw.is_some() {
+            write!(progress_out, "{CLEAR_LINE}")?;
+            progress_out.flush()?;
+        }
+        writeln!(message_out, "{message}")?;
+        message_out.flush()?;
+        if let Some(text) = redraw {
+            write!(progress_out, "{text}")?;
+            progress_out.flush()?;
+        }
+        drop(state);
+        Ok(())
+    }
+
+    /// Replace the progress line text and redraw it.
+    ///
+    /// # Errors
+    /// Returns an error if writing fails.
+    pub fn write_progress(&self, progress_out: &mut impl Write, text: &str) -> io::Result<()> {
+        let mut state = self.state();
+        if let Some(current) = state.as_mut() {
+            text.clone_into(current);
+            if self.interactive {
+                write!(progress_out, "{CLEAR_LINE}{text}")?;
+                progress_out.flush()?;
+            }
+        }
+        drop(state);
+        Ok(())
+    }
+
+    /// Clear the progress line and unregister it.
+    ///
+    /// # Errors
+    /// Returns an error if writing fails.
+    pub fn finish_progress(&self, progress_out: &mut impl Write) -> io::Result<()> {
+        if self.state().take().is_some() && self.interactive {
+            write!(progress_out, "{CLEAR_LINE}")?;
+            progress_out.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Same as [`Self::write_line`] for the print macros, so test output capturing still works.
+    fn print_with(&self, print: impl FnOnce()) {
+        let state = self.state();
+        let redraw = state.as_deref().filter(|_| self.interactive);
+        if redraw.is_some() {
+            print!("{CLEAR_LINE}");
+            let _ = io::stdout().flush();
+        }
+        print();
+        if let Some(text) = redraw {
+            print!("{text}");
+            let _ = io::stdout().flush();
+        }
+        drop(state);
+    }
+
+    fn state(&self) -> MutexGuard<'_, Option<String>> {
+        // The state is a plain string, so a panic while holding the lock can not leave it inconsistent
+        self.progress.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+impl ProgressLine<'_> {
+    /// Replace the progress text.
+    pub fn update(&self, text: &str) {
+        let _ = self.coordinator.write_progress(&mut io::stdout(), text);
+    }
+}
+
+impl Drop for ProgressLine<'_> {
+    fn drop(&mut self) {
+        let _ = self.coordinator.finish_progress(&mut io::stdout());
+    }
+}
+
+/// Print a line to stdout without corrupting an active progress line.
+pub fn print_line(message: &str) {
+    GLOBAL.print_with(|| println!("{message}"));
+}
+
+/// Print a warning in yellow to stderr without corrupting an active progress line.
+pub fn print_warning(message: &str) {
+    GLOBAL.print_with(|| eprintln!("{}", message.yellow()));
+}
+
+/// Print an error in red to stderr without corrupting an active progress line.
+pub fn print_error(message: &str) {
+    GLOBAL.print_with(|| eprintln!("{}", message.red()));
+}
+
+#[cfg(test)]
+mod output_tests {
+    use super::*;
+
+    fn write_line(coordinator: &OutputCoordinator, message: &str) -> (String, String) {
+        let mut progress_out = Vec::new();
+        let mut message_out = Vec::new();
+        coordinator
+            .write_line(&mut progress_out, &mut message_out, message)
+            .unwrap();
+        (
+            String::from_utf8(progress_out).unwrap(),
+            String::from_utf8(message_out).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_non_interactive_output_has_no_escape_sequences() {
+        let coordinator = OutputCoordinator::new(false);
+        assert_eq!(
+            write_line(&coordinator, "before"),
+            (String::new(), "before\n".to_string())
+        );
+
+        let mut progress_out = Vec::new();
+        coordinator.state().replace(String::new());
+        coordinator.write_progress(&mut progress_out, "[1/2]").unwrap();
+        assert!(coordinator.has_progress());
+        assert_eq!(
+            write_line(&coordinator, "during"),
+            (String::new(), "during\n".to_string())
+        );
+        coordinator.finish_progress(&mut progress_out).unwrap();
+        assert!(!coordinator.has_progress());
+        assert!(progress_out.is_empty());
+    }
+
+    #[test]
+    fn test_interactive_output_redraws_progress() {
+        let coordinator = OutputCoordinator::new(true);
+        assert_eq!(write_line(&coordinator, "idle"), (String::new(), "idle\n".to_string()));
+
+        let mut progress_out = Vec::new();
+        coordinator.state().replace(String::new());
+        coordinator.write_progress(&mut progress_out, "[1/2]").unwrap();
+        assert_eq!(String::from_utf8_lossy(&progress_out), format!("{CLEAR_LINE}[1/2]"));
+        assert_eq!(
+            write_line(&coordinator, "warning"),
+            (format!("{CLEAR_LINE}[1/2]"), "warning\n".to_string())
+        );
+
+        progress_out.clear();
+        coordinator.finish_progress(&mut progress_out).unwrap();
+        assert_eq!(String::from_utf8_lossy(&progress_out), CLEAR_LINE);
+        assert_eq!(write_line(&coordinator, "done"), (String::new(), "done\n".to_string()));
+    }
+
+    #[test]
+    fn test_progress_updates_without_active_progress_are_ignored() {
+        let coordinator = OutputCoordinator::new(true);
+        let mut progress_out = Vec::new();
+        coordinator.write_progress(&mut progress_out, "[1/2]").unwrap();
+        coordinator.finish_progress(&mut progress_out).unwrap();
+        assert!(progress_out.is_empty());
+        assert!(!coordinator.has_progress());
+    }
+}
@@ -0,0 +1,143 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+const PROJECT_NAME: &str = env!("CARGO_PKG_NAME");
+
+/// Environment variable for overriding the state directory, for example in tests.
+pub const STATE_DIR_ENV: &str = "CLI_TOOLS_STATE_DIR";
+
+/// Get the state directory for the given tool, creating it if needed.
+///
+/// Uses the path from the `CLI_TOOLS_STATE_DIR` environment variable if it is set.
+/// Otherwise, uses `<PROJECT_NAME>/<tool>` in the platform state directory,
+/// for example `$XDG_STATE_HOME` on Linux,
+/// and falls back to the platform cache directory on other systems.
+pub fn state_dir(tool: &str) -> Result<PathBuf> {
+    let base = if let Some(path) = env::var_os(STATE_DIR_ENV).filter(|path| !path.is_empty()) {
+        PathBuf::from(path)
+    } else {
+        dirs::state_dir()
+            .or_else(dirs::cache_dir)
+            .context("Failed to get state directory")?
+            .join(PROJECT_NAME)
+    };
+    tool_state_dir(&base, tool)
+}
+
+/// Load the named state for the given tool.
+///
+/// Returns the default value if the state does not exist or can not be read.
+#[must_use]
+pub fn load_state<T: DeserializeOwned + Default>(tool: &str, name: &str) -> T {
+    match state_dir(tool) {
+        Ok(dir) => load_state_from(&dir, name),
+        Err(error) => {
+            eprintln!("{}", format!("Failed to load {tool} state: {error:#}").yellow());
+            T::default()
+        }
+    }
+}
+
+/// Save the named state for the given tool.
+///
+/// The state is written to a temporary file first and then moved in place,
+/// so an interrupted write does not leave a partial state file behind.
+pub fn save_state<T: Serialize>(tool: &str, name: &str, value: &T) -> Result<()> {
+    save_state_to(&state_dir(tool)?, name, value)
+}
+
+fn tool_state_dir(base: &Path, tool: &str) -> Result<PathBuf> {
+    let dir = base.join(tool);
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create state directory: {}", dir.display()))?;
+    Ok(dir)
+}
+
+fn state_file(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{name}.json"))
+}
+
+fn load_state_from<T: DeserializeOwned + Default>(dir: &Path, name: &str) -> T {
+    let path = state_file(dir, name);
+    if !path.is_file() {
+        return T::default();
+    }
+    let state = fs::read_to_string(&path)
+        .context("Failed to read state file")
+        .and_then(|content| serde_json::from_str(&content).context("Invalid state file"));
+    state.unwrap_or_else(|error| {
+        eprintln!("{}", format!("{error:#}: {}", path.display()).yellow());
+        T::default()
+    })
+}
+
+fn save_state_to<T: Serialize>(dir: &Path, name: &str, value: &T) -> Result<()> {
+    let path = state_file(dir, name);
+    let content = serde_json::to_string_pretty(value).context("Failed to serialize state")?;
+    let mut file = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("Failed to create temporary state file in {}", dir.display()))?;
+    file.write_all(content.as_bytes())
+        .context("Failed to write temporary state file")?;
+    file.persist(&path)
+        .with_context(|| format!("Failed to write state file: {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod state_tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_missing_state_is_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let state: BTreeMap<String, u32> = load_state_from(dir.path(), "missing");
+        assert!(state.is_empty());
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_dir = tool_state_dir(dir.path(), "tool").unwrap();
+        let state = BTreeMap::from([("a".to_string(), 1), ("b".to_string(), 2)]);
+        save_state_to(&state_dir, "counts", &state).unwrap();
+        assert!(state_dir.join("counts.json").is_file());
+
+        let loaded: BTreeMap<String, u32> = load_state_from(&state_dir, "counts");
+        assert_eq!(loaded, state);
+
+        save_state_to(&state_dir, "counts", &BTreeMap::from([("c".to_string(), 3)])).unwrap();
+        let loaded: BTreeMap<String, u32> = load_state_from(&state_dir, "counts");
+        assert_eq!(loaded, BTreeMap::from([("c".to_string(), 3)]));
+        assert_eq!(fs::read_dir(&state_dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_state_dir_env_override() {
+        let dir = tempfile::tempdir().unwrap();
+        env::set_var(STATE_DIR_ENV, dir.path());
+        let state_path = state_dir("tool").unwrap();
+        save_state("tool", "counts", &BTreeMap::from([("a".to_string(), 1)])).unwrap();
+        let loaded: BTreeMap<String, u32> = load_state("tool", "counts");
+        env::remove_var(STATE_DIR_ENV);
+
+        assert_eq!(state_path, dir.path().join("tool"));
+        assert!(state_path.join("counts.json").is_file());
+        assert_eq!(loaded, BTreeMap::from([("a".to_string(), 1)]));
+    }
+
+    #[test]
+    fn test_invalid_state_is_default() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("broken.json"), "{ not json").unwrap();
+        let state: Vec<String> = load_state_from(dir.path(), "broken");
+        assert!(state.is_empty());
+    }
+}
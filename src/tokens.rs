@@ -0,0 +1,124 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+/// Video resolution like "1080p", "1920x1080", or "4K".
+pub static RE_RESOLUTION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)^(?:\d{3,4}[pi]|\d{3,4}x\d{3,4}|[48]k|uhd)$").expect("Failed to compile resolution regex")
+});
+
+/// Short words that join other words and do not carry meaning on their own.
+pub const GLUE_WORDS: [&str; 16] = [
+    "&", "a", "an", "and", "at", "by", "feat", "for", "ft", "in", "of", "on", "or", "the", "to", "with",
+];
+
+/// Split a dot-separated name into its non-empty parts.
+///
+/// ```rust
+/// use cli_tools::tokens::split_dot_tokens;
+///
+/// assert_eq!(split_dot_tokens("Some..Name.2024."), vec!["Some", "Name", "2024"]);
+/// ```
+#[must_use]
+pub fn split_dot_tokens(name: &str) -> Vec<&str> {
+    name.split('.').filter(|token| !token.is_empty()).collect()
+}
+
+/// Check if the token is a video resolution.
+#[must_use]
+pub fn is_resolution_token(token: &str) -> bool {
+    RE_RESOLUTION.is_match(token)
+}
+
+/// Check if the token only contains digits.
+#[must_use]
+pub fn is_numeric_token(token: &str) -> bool {
+    !token.is_empty() && token.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Check if the token is a glue word, ignoring case.
+#[must_use]
+pub fn is_glue_word(token: &str) -> bool {
+    GLUE_WORDS.iter().any(|word| word.eq_ignore_ascii_case(token))
+}
+
+/// Remove resolutions, numbers, and glue words, keeping the meaningful tokens.
+#[must_use]
+pub fn filter_noise_tokens<'a>(tokens: &[&'a str]) -> Vec<&'a str> {
+    tokens
+        .iter()
+        .copied()
+        .filter(|token| !is_resolution_token(token) && !is_numeric_token(token) && !is_glue_word(token))
+        .collect()
+}
+
+/// Get all sequences of `n` consecutive tokens joined with dots.
+///
+/// ```rust
+/// use cli_tools::tokens::n_part_sequences;
+///
+/// assert_eq!(n_part_sequences(&["a", "b", "c"], 2), vec!["a.b", "b.c"]);
+/// ```
+#[must_use]
+pub fn n_part_sequences(tokens: &[&str], n: usize) -> Vec<String> {
+    if n == 0 {
+        return Vec::new();
+    }
+    tokens.windows(n).map(|window| window.join(".")).collect()
+}
+
+#[cfg(test)]
+mod tokens_tests {
+    use super::*;
+
+    #[test]
+    fn test_split_dot_tokens() {
+        assert_eq!(split_dot_tokens("Some.Name.1080p"), vec!["Some", "Name", "1080p"]);
+        assert_eq!(split_dot_tokens(".Some..Name."), vec!["Some", "Name"]);
+        assert!(split_dot_tokens("").is_empty());
+        assert!(split_dot_tokens("...").is_empty());
+    }
+
+    #[test]
+    fn test_is_resolution_token() {
+        for token in ["1080p", "720P", "2160p", "1080i", "1920x1080", "4K", "8k", "UHD"] {
+            assert!(is_resolution_token(token), "{token}");
+        }
+        for token in ["1080", "p1080", "x265", "1080p60", "Name"] {
+            assert!(!is_resolution_token(token), "{token}");
+        }
+    }
+
+    #[test]
+    fn test_is_numeric_token() {
+        assert!(is_numeric_token("2024"));
+        assert!(is_numeric_token("01"));
+        assert!(!is_numeric_token(""));
+        assert!(!is_numeric_token("2024a"));
+        assert!(!is_numeric_token("-1"));
+    }
+
+    #[test]
+    fn test_is_glue_word() {
+        assert!(is_glue_word("and"));
+        assert!(is_glue_word("The"));
+        assert!(is_glue_word("&"));
+        assert!(!is_glue_word("Band"));
+    }
+
+    #[test]
+    fn test_filter_noise_tokens() {
+        let tokens = split_dot_tokens("The.Artist.and.Band.Live.At.Venue.2024.1080p");
+        assert_eq!(filter_noise_tokens(&tokens), vec!["Artist", "Band", "Live", "Venue"]);
+    }
+
+    #[test]
+    fn test_n_part_sequences() {
+        let tokens = ["Some", "Long", "Name"];
+        assert_eq!(n_part_sequences(&tokens, 1), vec!["Some", "Long", "Name"]);
+        assert_eq!(n_part_sequences(&tokens, 2), vec!["Some.Long", "Long.Name"]);
+        assert_eq!(n_part_sequences(&tokens, 3), vec!["Some.Long.Name"]);
+        assert!(n_part_sequences(&tokens, 4).is_empty());
+        assert!(n_part_sequences(&tokens, 0).is_empty());
+    }
+}
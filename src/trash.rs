@@ -0,0 +1,253 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use walkdir::WalkDir;
+
+/// Section header of a trash info file.
+const TRASH_INFO_HEADER: &str = "[Trash Info]";
+
+/// Move a file or directory to the trash of the current user so it can be restored later.
+///
+/// On Linux the file is moved to the home trash following the freedesktop.org
+/// trash specification, with a `.trashinfo` file that stores the original path.
+/// Other platforms use the system trash, like the Recycle Bin on Windows and the Trash on macOS.
+pub fn move_to_trash(path: &Path) -> Result<()> {
+    if !path.exists() && !path.is_symlink() {
+        anyhow::bail!("Path does not exist: {}", path.display());
+    }
+    #[cfg(target_os = "linux")]
+    {
+        move_to_trash_dir(path, &home_trash_dir()?).map(|_| ())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        ::trash::delete(path).with_context(|| format!("Failed to move to trash: {}", path.display()))
+    }
+}
+
+/// Home trash directory: `$XDG_DATA_HOME/Trash`, usually `~/.local/share/Trash`.
+#[cfg(target_os = "linux")]
+fn home_trash_dir() -> Result<PathBuf> {
+    dirs::data_dir()
+        .map(|dir| dir.join("Trash"))
+        .context("Failed to get the home trash directory")
+}
+
+/// Move a path into a freedesktop.org trash directory.
+///
+/// The path is stored under `files` and its original location under `info`.
+/// Names already used in the trash get a numeric suffix.
+/// Paths on a different file system are copied and then removed.
+/// Returns the new path of the file in the trash.
+pub fn move_to_trash_dir(path: &Path, trash_dir: &Path) -> Result<PathBuf> {
+    let original = std::path::absolute(path).with_context(|| format!("Failed to resolve path: {}", path.display()))?;
+    let files_dir = trash_dir.join("files");
+    let info_dir = trash_dir.join("info");
+    for dir in [&files_dir, &info_dir] {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create trash directory: {}", dir.display()))?;
+    }
+
+    let file_name = original
+        .file_name()
+        .with_context(|| format!("Failed to get file name: {}", path.display()))?;
+    let info = format!(
+        "{TRASH_INFO_HEADER}\nPath={}\nDeletionDate={}\n",
+        percent_encode_path(&original),
+        Local::now().format("%Y-%m-%dT%H:%M:%S")
+    );
+    let (info_path, trashed_path) = reserve_trash_name(&files_dir, &info_dir, file_name, &info)?;
+
+    if let Err(error) = move_path(&original, &trashed_path) {
+        let _ = fs::remove_file(&info_path);
+        return Err(error).with_context(|| format!("Failed to move to trash: {}", path.display()));
+    }
+    Ok(trashed_path)
+}
+
+/// Create the info file with a free name, which reserves the same name in the files directory.
+fn reserve_trash_name(files_dir: &Path, info_dir: &Path, file_name: &OsStr, info: &str) -> Result<(PathBuf, PathBuf)> {
+    for number in 1_usize.. {
+        let name = if number == 1 {
+            file_name.to_os_string()
+        } else {
+            numbered_name(file_name, number)
+        };
+        let trashed_path = files_dir.join(&name);
+        let mut info_name = name;
+        info_name.push(".trashinfo");
+        let info_path = info_dir.join(info_name);
+        if trashed_path.exists() || trashed_path.is_symlink() {
+            continue;
+        }
+        match fs::OpenOptions::new().write(true).create_new(true).open(&info_path) {
+            Ok(mut file) => {
+                io::Write::write_all(&mut file, info.as_bytes())
+                    .with_context(|| format!("Failed to write trash info: {}", info_path.display()))?;
+                return Ok((info_path, trashed_path));
+            }
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(error) => {
+                return Err(error).with_context(|| format!("Failed to create trash info: {}", info_path.display()));
+            }
+        }
+    }
+    unreachable!("ran out of trash file names")
+}
+
+/// Add a number before the extension: "file.txt" becomes "file.2.txt".
+fn numbered_name(file_name: &OsStr, number: usize) -> OsString {
+    let path = Path::new(file_name);
+    let mut name = path.file_stem().unwrap_or(file_name).to_os_string();
+    name.push(format!(".{number}"));
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    name
+}
+
+/// Rename the path, or copy and remove it when the target is on a different file system.
+fn move_path(from: &Path, to: &Path) -> Result<()> {
+    if !from.is_dir() || from.is_symlink() {
+        return crate::move_file(from, to, false);
+    }
+    match fs::rename(from, to) {
+        Err(error) if error.kind() == io::ErrorKind::CrossesDevices => copy_dir_and_remove(from, to),
+        result => result.with_context(|| format!("Failed to move {} to {}", from.display(), to.display())),
+    }
+}
+
+/// Copy a directory tree with the file metadata to the target and remove the original after a successful copy.
+fn copy_dir_and_remove(from: &Path, to: &Path) -> Result<()> {
+    for entry in WalkDir::new(from) {
+        let entry = entry?;
+        let target = to.join(entry.path().strip_prefix(from)?);
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target).with_context(|| format!("Failed to create directory: {}", target.display()))?;
+        } else {
+            crate::copy_preserving_metadata(entry.path(), &target)?;
+        }
+    }
+    fs::remove_dir_all(from).with_context(|| format!("Failed to remove directory: {}", from.display()))
+}
+
+/// Percent-encode a path for the trash info file, keeping unreserved characters and slashes as is.
+fn percent_encode_path(path: &Path) -> String {
+    let mut encoded = String::new();
+    for byte in path.as_os_str().as_encoded_bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(byte) {
+            encoded.push(char::from(*byte));
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod trash_tests {
+    use super::*;
+
+    fn trash_info(trash_dir: &Path, name: &str) -> String {
+        fs::read_to_string(trash_dir.join("info").join(format!("{name}.trashinfo"))).unwrap()
+    }
+
+    #[test]
+    fn test_move_file_to_trash() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash_dir = dir.path().join("Trash");
+        let file = dir.path().join("some file.txt");
+        fs::write(&file, "content").unwrap();
+
+        let trashed = move_to_trash_dir(&file, &trash_dir).unwrap();
+        assert_eq!(trashed, trash_dir.join("files").join("some file.txt"));
+        assert!(!file.exists());
+        assert_eq!(fs::read_to_string(&trashed).unwrap(), "content");
+
+        let info = trash_info(&trash_dir, "some file.txt");
+        let mut lines = info.lines();
+        assert_eq!(lines.next(), Some(TRASH_INFO_HEADER));
+        assert_eq!(
+            lines.next(),
+            Some(format!("Path={}", percent_encode_path(&file)).as_str())
+        );
+        assert!(lines.next().unwrap().starts_with("DeletionDate="));
+    }
+
+    #[test]
+    fn test_name_collisions_get_numbered() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash_dir = dir.path().join("Trash");
+        let file = dir.path().join("file.txt");
+        for number in 1..=3 {
+            fs::write(&file, number.to_string()).unwrap();
+            move_to_trash_dir(&file, &trash_dir).unwrap();
+        }
+        let files_dir = trash_dir.join("files");
+        assert_eq!(fs::read_to_string(files_dir.join("file.txt")).unwrap(), "1");
+        assert_eq!(fs::read_to_string(files_dir.join("file.2.txt")).unwrap(), "2");
+        assert_eq!(fs::read_to_string(files_dir.join("file.3.txt")).unwrap(), "3");
+        assert!(trash_info(&trash_dir, "file.3.txt").contains("/file.txt\n"));
+    }
+
+    #[test]
+    fn test_move_directory_to_trash() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash_dir = dir.path().join("Trash");
+        let source = dir.path().join("Folder");
+        fs::create_dir_all(source.join("nested")).unwrap();
+        fs::write(source.join("nested").join("file.txt"), "content").unwrap();
+
+        let trashed = move_to_trash_dir(&source, &trash_dir).unwrap();
+        assert!(!source.exists());
+        assert_eq!(
+            fs::read_to_string(trashed.join("nested").join("file.txt")).unwrap(),
+            "content"
+        );
+        assert!(move_to_trash(&source).is_err());
+    }
+
+    #[test]
+    fn test_copy_dir_and_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        fs::create_dir_all(source.join("nested")).unwrap();
+        fs::write(source.join("file.txt"), "first").unwrap();
+        fs::write(source.join("nested").join("file.txt"), "second").unwrap();
+        let modified = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000);
+        crate::set_modified_time(&source.join("file.txt"), modified).unwrap();
+        let target = dir.path().join("target");
+
+        copy_dir_and_remove(&source, &target).unwrap();
+        assert!(!source.exists());
+        assert_eq!(fs::read_to_string(target.join("file.txt")).unwrap(), "first");
+        assert_eq!(
+            fs::metadata(target.join("file.txt")).unwrap().modified().unwrap(),
+            modified
+        );
+        assert_eq!(
+            fs::read_to_string(target.join("nested").join("file.txt")).unwrap(),
+            "second"
+        );
+    }
+
+    #[test]
+    fn test_percent_encode_path() {
+        assert_eq!(
+            percent_encode_path(Path::new("/home/user/My File ä#1.txt")),
+            "/home/user/My%20File%20%C3%A4%231.txt"
+        );
+    }
+
+    #[test]
+    fn test_numbered_name() {
+        assert_eq!(numbered_name("file.txt".as_ref(), 2), "file.2.txt");
+        assert_eq!(numbered_name("Folder".as_ref(), 3), "Folder.3");
+        assert_eq!(numbered_name(".hidden".as_ref(), 2), ".hidden.2");
+    }
+}